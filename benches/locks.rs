@@ -0,0 +1,78 @@
+use std::cell::UnsafeCell;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use shmoo::error::Result;
+use shmoo::sync::{QueueLock, Spinlock, TicketLock};
+
+const ITERS: u64 = 1000;
+
+trait Lock: Default {
+    fn lock(&mut self) -> Result<()>;
+    fn unlock(&mut self) -> Result<()>;
+}
+
+macro_rules! impl_lock {
+    ($($ty:ty),*) => {
+        $(impl Lock for $ty {
+            fn lock(&mut self) -> Result<()> {
+                <$ty>::lock(self)
+            }
+
+            fn unlock(&mut self) -> Result<()> {
+                <$ty>::unlock(self)
+            }
+        })*
+    };
+}
+
+impl_lock!(Spinlock, TicketLock, QueueLock<16>);
+
+/// Mimics a lock living in shared memory that several processes access through their
+/// own mutable references.
+struct Shared<L> {
+    lock: UnsafeCell<L>,
+    count: UnsafeCell<u64>,
+}
+
+unsafe impl<L> Sync for Shared<L> {}
+
+fn contend<L: Lock>(shared: &Shared<L>, threads: u64) {
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..ITERS {
+                    let lock = unsafe { &mut *shared.lock.get() };
+                    lock.lock().unwrap();
+                    unsafe { *shared.count.get() += 1 };
+                    lock.unlock().unwrap();
+                }
+            });
+        }
+    });
+}
+
+fn bench_lock<L: Lock>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("{}_contention", name));
+    for threads in [1, 2, 4, 8] {
+        let shared = Shared {
+            lock: UnsafeCell::new(L::default()),
+            count: UnsafeCell::new(0),
+        };
+        group.throughput(Throughput::Elements(threads * ITERS));
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &n| {
+            b.iter(|| contend(&shared, n))
+        });
+    }
+    group.finish();
+}
+
+fn bench(c: &mut Criterion) {
+    bench_lock::<Spinlock>(c, "spinlock");
+    bench_lock::<TicketLock>(c, "ticket_lock");
+    bench_lock::<QueueLock<16>>(c, "queue_lock");
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -12,20 +12,15 @@ const DONE: &[u8] = b"done";
 
 fn bench(c: &mut Criterion) {
     let n = 1000;
-    let mut shm = Shm::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .mode(0o644)
-        .map("/shmoo", std::mem::size_of::<Shmbuf<4>>())
-        .unwrap();
+    let mut shm = Shm::anonymous(std::mem::size_of::<Shmbuf<4>>()).unwrap();
+
+    let mut cmd = Command::new("target/release/examples/ping");
+    shm.inherit(&mut cmd).unwrap();
 
     let shmbuf = shm.construct_mut::<Shmbuf<4>>().unwrap();
     let mut buf = vec![0u8; 4];
 
-    let mut peer = Command::new("target/release/examples/ping")
-        .spawn()
-        .unwrap();
+    let mut peer = cmd.spawn().unwrap();
 
     let mut group = c.benchmark_group("ping_pong_throughput");
     group.throughput(Throughput::Elements(n));
@@ -1,9 +1,7 @@
-pub mod msg_queue;
-
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 use std::process::Command;
 
-use msg_queue::MsgQueue;
+use shmoo::queue::MsgQueue;
 
 type Msg = [u8; 4];
 
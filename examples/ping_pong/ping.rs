@@ -8,7 +8,8 @@ const PONG: &[u8] = b"pong";
 const DONE: &[u8] = b"done";
 
 fn main() {
-    let mut mem = Shm::open("/shmoo").unwrap();
+    // The descriptor was passed by pong, and nothing else in this process owns it.
+    let mut mem = unsafe { Shm::from_inherited() }.unwrap();
 
     let shmbuf = Shmbuf::<4>::from_shm_mut(&mut mem).unwrap();
     let mut buf = vec![0u8; 4];
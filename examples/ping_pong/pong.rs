@@ -13,21 +13,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = std::env::args().collect();
     let n = args[1].parse::<u32>().unwrap();
 
-    let mut shm = Shm::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .map("/shmoo", std::mem::size_of::<Shmbuf<4>>())?;
-
-    let shmbuf = shm.construct_mut::<Shmbuf<4>>()?;
-    let mut buf = vec![0u8; 4];
+    let mut shm = Shm::anonymous(std::mem::size_of::<Shmbuf<4>>())?;
 
     #[cfg(debug_assertions)]
     let target = "target/debug/examples/ping";
     #[cfg(not(debug_assertions))]
     let target = "target/release/examples/ping";
 
-    let mut peer = Command::new(target).spawn()?;
+    let mut cmd = Command::new(target);
+    shm.inherit(&mut cmd)?;
+
+    let shmbuf = shm.construct_mut::<Shmbuf<4>>()?;
+    let mut buf = vec![0u8; 4];
+
+    let mut peer = cmd.spawn()?;
 
     for _ in 0..n {
         // Wait for ping to post.
@@ -22,13 +22,3 @@ impl<const N: usize> Shmbuf<N> {
         self.buf[..buf.len()].copy_from_slice(buf);
     }
 }
-
-impl<const N: usize> Default for Shmbuf<N> {
-    fn default() -> Self {
-        Self {
-            sem1: BinarySemaphore::new(),
-            sem2: BinarySemaphore::new(),
-            buf: [0; N],
-        }
-    }
-}
@@ -1,6 +1,4 @@
-pub mod msg_queue;
-
-use msg_queue::MsgQueue;
+use shmoo::queue::MsgQueue;
 
 type Msg = [u8; 4];
 
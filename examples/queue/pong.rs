@@ -1,6 +1,4 @@
-pub mod msg_queue;
-
-use msg_queue::MsgQueue;
+use shmoo::queue::MsgQueue;
 use std::{error::Error, process::Command};
 
 type Msg = [u8; 4];
@@ -22,7 +20,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     #[cfg(not(debug_assertions))]
     let target = "target/release/examples/queue_ping";
 
-    let mut peer = Command::new(target).spawn().unwrap();
+    let mut peer = Command::new(target).spawn()?;
 
     for _ in 0..n {
         let msg = rx.recv()?;
@@ -1,84 +1,453 @@
-use proc_macro2::{Span, TokenStream};
-use quote::quote;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
 use syn::{
-    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, GenericParam,
-    Generics, Result,
+    parenthesized, parse_macro_input, parse_quote, token, Attribute, Data, DataEnum, DeriveInput,
+    Error, Expr, Field, GenericParam, Generics, Ident, LitInt, Path, Result, TypeParamBound,
+    Variant,
 };
 
-#[proc_macro_derive(ShmInit)]
-pub fn derive_to_shm(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+#[proc_macro_derive(ShmSafe)]
+pub fn derive_shm_safe(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    match expand_shm_safe(input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn expand_shm_safe(input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
 
-    let name = input.ident;
+    check_repr_c(&input, "ShmSafe")?;
 
-    let generics = add_trait_bounds(input.generics);
+    let generics = add_trait_bounds(input.generics.clone(), parse_quote!(shmoo::ShmSafe));
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let err = check_repr_c(&input.attrs, &name.span(), "ShmInit");
-    if err.is_err() {
-        return err.err().unwrap().into_compile_error().into();
+    let assert_fields = assert_fields_shm_safe(&input);
+
+    Ok(quote! {
+        #assert_fields
+
+        unsafe impl #impl_generics shmoo::ShmSafe for #name #ty_generics #where_clause {}
+    })
+}
+
+/// Fails to compile, pointing at the field, if any field is not `ShmSafe`.
+fn assert_fields_shm_safe(input: &DeriveInput) -> TokenStream {
+    let generics = add_trait_bounds(input.generics.clone(), parse_quote!(shmoo::ShmSafe));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let name = &input.ident;
+    let fields: Vec<&Field> = match input.data {
+        Data::Struct(ref data) => data.fields.iter().collect(),
+        Data::Enum(ref data) => data.variants.iter().flat_map(|v| &v.fields).collect(),
+        Data::Union(ref data) => data.fields.named.iter().collect(),
+    };
+    let asserts = fields.iter().map(|field| {
+        let ty = &field.ty;
+        quote_spanned! {ty.span()=>
+            assert_shm_safe::<#ty>();
+        }
+    });
+    quote! {
+        const _: () = {
+            fn assert_shm_safe<T: ?Sized + shmoo::ShmSafe>() {}
+
+            // Taking `Self` brings in its implied bounds, like `T: 'a` for `&'a T`.
+            #[allow(dead_code)]
+            fn assert_fields #impl_generics (_: ::core::marker::PhantomData<#name #ty_generics>) #where_clause {
+                #(#asserts)*
+            }
+        };
+    }
+}
+
+#[proc_macro_derive(ShmInit, attributes(shm))]
+pub fn derive_to_shm(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_shm_init(input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.into_compile_error().into(),
     }
+}
+
+fn expand_shm_init(input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+
+    let repr = check_repr_c(&input, "ShmInit")?;
+
+    // Fields of a type parameter's type must be `ShmSafe` and are initialized with
+    // `InitInPlace`.
+    let generics = add_trait_bounds(input.generics.clone(), parse_quote!(shmoo::ShmSafe));
+    let generics = add_trait_bounds(generics, parse_quote!(shmoo::InitInPlace));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let to_shm = to_shm_impl(&input.data, false);
-    let to_shm_mut = to_shm_impl(&input.data, true);
+    let assert_fields = assert_fields_shm_safe(&input);
+
+    let capacity = match parse_capacity(&input.attrs)? {
+        Some(capacity) => quote! {
+            const CAPACITY: usize = {
+                let capacity: usize = #capacity;
+                assert!(capacity >= size_of::<Self>(), "capacity must fit the struct");
+                capacity
+            };
+        },
+        None => quote! {},
+    };
+    let init_in_place = init_in_place_impl(&input.data, &repr)?;
+    let to_shm_mut = to_shm_impl();
+
+    Ok(quote! {
+        #assert_fields
+
+        unsafe impl #impl_generics shmoo::InitInPlace for #name #ty_generics #where_clause {
+            #init_in_place
+        }
 
-    let expanded = quote! {
         unsafe impl #impl_generics shmoo::ShmInit for #name #ty_generics #where_clause {
+            #capacity
+
             fn shm_init(shm: &mut shmoo::Shm) -> shmoo::error::Result<&Self> {
-                #to_shm
+                Self::shm_init_mut(shm).map(|obj| &*obj)
             }
 
             fn shm_init_mut(shm: &mut shmoo::Shm) -> shmoo::error::Result<&mut Self> {
                 #to_shm_mut
             }
         }
-    };
+    })
+}
 
-    proc_macro::TokenStream::from(expanded)
+/// How a field is initialized, from its `#[shm(...)]` attribute.
+enum FieldInit {
+    /// Zero-filled, then initialized with `InitInPlace`.
+    InPlace,
+    /// `#[shm(default = expr)]`: set to `expr`.
+    Default(Expr),
+    /// `#[shm(zeroed)]`: zero-filled only.
+    Zeroed,
+    /// `#[shm(init = path)]`: zero-filled, then passed to `path`.
+    Init(Path),
+    /// `#[shm(skip)]`: left as it is in the segment.
+    Skip,
 }
 
-fn to_shm_impl(data: &Data, is_mut: bool) -> TokenStream {
-    match *data {
-        Data::Struct(ref data) => match data.fields {
-            Fields::Named(_) | Fields::Unnamed(_) | Fields::Unit => {
-                let mut_tok = if is_mut {
-                    quote! { mut }
-                } else {
-                    quote! {}
+fn parse_field_init(field: &Field) -> Result<Option<FieldInit>> {
+    let mut init = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("shm"))
+    {
+        attr.parse_nested_meta(|meta| {
+            let parsed = if meta.path.is_ident("default") {
+                FieldInit::Default(meta.value()?.parse()?)
+            } else if meta.path.is_ident("zeroed") {
+                FieldInit::Zeroed
+            } else if meta.path.is_ident("init") {
+                FieldInit::Init(meta.value()?.parse()?)
+            } else if meta.path.is_ident("skip") {
+                FieldInit::Skip
+            } else {
+                return Err(meta.error("expected `default`, `zeroed`, `init` or `skip`"));
+            };
+            if init.replace(parsed).is_some() {
+                return Err(meta.error("field has more than one shm initializer"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(init)
+}
+
+fn parse_capacity(attrs: &[Attribute]) -> Result<Option<Expr>> {
+    let mut capacity = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("shm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("capacity") {
+                capacity = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `capacity`"))
+            }
+        })?;
+    }
+    Ok(capacity)
+}
+
+/// Returns whether zeroed memory is already a valid value for `field`, and the
+/// statements that initialize it at `place`, a `*mut` to the field.
+fn field_init(
+    field: &Field,
+    init: FieldInit,
+    place: TokenStream,
+    packed: bool,
+) -> Result<(TokenStream, TokenStream)> {
+    if packed {
+        return packed_field_init(field, init, place);
+    }
+    let ty = &field.ty;
+    Ok(match init {
+        FieldInit::InPlace => (
+            quote! { <#ty as shmoo::InitInPlace>::ZEROED },
+            quote! {
+                let field = #place;
+                field.write_bytes(0, 1);
+                <#ty as shmoo::InitInPlace>::init_in_place(field)?;
+            },
+        ),
+        FieldInit::Default(expr) => (quote! { false }, quote! { #place.write(#expr); }),
+        FieldInit::Zeroed => (quote! { true }, quote! { #place.write_bytes(0, 1); }),
+        FieldInit::Init(path) => (
+            quote! { false },
+            quote! {
+                let field = #place;
+                field.write_bytes(0, 1);
+                #path(&mut *field.cast::<::core::mem::MaybeUninit<#ty>>())?;
+            },
+        ),
+        FieldInit::Skip => (quote! { true }, quote! {}),
+    })
+}
+
+/// Like [`field_init`], for a field of a packed struct, which may be unaligned. It
+/// can only be zero-filled or written with `write_unaligned`, so fields that need
+/// `InitInPlace` or an `init` function are rejected.
+fn packed_field_init(
+    field: &Field,
+    init: FieldInit,
+    place: TokenStream,
+) -> Result<(TokenStream, TokenStream)> {
+    let ty = &field.ty;
+    let zero = quote! { #place.cast::<u8>().write_bytes(0, size_of::<#ty>()); };
+    Ok(match init {
+        FieldInit::InPlace => (
+            quote! { true },
+            quote! {
+                const {
+                    assert!(
+                        <#ty as shmoo::InitInPlace>::ZEROED,
+                        "fields of a packed struct must be valid when zeroed or have an shm initializer",
+                    )
                 };
-                quote! {
-                    let ptr = shm[..size_of::<Self>()].as_mut_ptr() as *mut Self;
-                    assert!(ptr.is_aligned());
-                    unsafe {
-                        ptr.write(Self::default());
-                        Ok(&#mut_tok *ptr)
-                    }
+                #zero
+            },
+        ),
+        FieldInit::Default(expr) => (quote! { false }, quote! { #place.write_unaligned(#expr); }),
+        FieldInit::Zeroed => (quote! { true }, zero),
+        FieldInit::Init(_) => {
+            return Err(Error::new_spanned(
+                field,
+                "fields of a packed struct may be unaligned, so they cannot have an `init` function",
+            ))
+        }
+        FieldInit::Skip => (quote! { true }, quote! {}),
+    })
+}
+
+/// Initializes each field in place, so that no field is ever built on the stack.
+fn init_in_place_impl(data: &Data, repr: &Repr) -> Result<TokenStream> {
+    let (zeroed, inits) = match *data {
+        Data::Struct(ref data) => {
+            let mut zeroed = Vec::new();
+            let mut inits = Vec::new();
+            for (field, member) in data.fields.iter().zip(data.fields.members()) {
+                let init = parse_field_init(field)?.unwrap_or(FieldInit::InPlace);
+                let place = quote! { ::core::ptr::addr_of_mut!((*ptr).#member) };
+                let (is_zeroed, init) = field_init(field, init, place, repr.packed)?;
+                zeroed.push(is_zeroed);
+                inits.push(init);
+            }
+            (zeroed, inits)
+        }
+        Data::Enum(ref data) => enum_init(data, repr)?,
+        Data::Union(ref data) => {
+            // The union is zero-filled, then at most one field is initialized.
+            let mut zeroed = vec![quote! { true }];
+            let mut inits = vec![quote! { ptr.write_bytes(0, 1); }];
+            for field in &data.fields.named {
+                let Some(init) = parse_field_init(field)? else {
+                    continue;
+                };
+                if inits.len() > 1 {
+                    return Err(Error::new_spanned(
+                        field,
+                        "only one union field can have an shm initializer",
+                    ));
                 }
+                let ident = &field.ident;
+                let place = quote! { ::core::ptr::addr_of_mut!((*ptr).#ident) };
+                let (is_zeroed, init) = field_init(field, init, place, repr.packed)?;
+                zeroed.push(is_zeroed);
+                inits.push(init);
             }
-        },
-        Data::Enum(_) => unimplemented!(),
-        Data::Union(_) => unimplemented!(),
+            (zeroed, inits)
+        }
+    };
+    Ok(quote! {
+        const ZEROED: bool = true #(&& #zeroed)*;
+
+        unsafe fn init_in_place(ptr: *mut Self) -> shmoo::error::Result<()> {
+            #(
+                unsafe {
+                    #inits
+                }
+            )*
+            Ok(())
+        }
+    })
+}
+
+/// Writes the tag of the default variant, the one marked `#[shm(default)]` or else
+/// the first, and initializes its fields where the enum's layout puts them.
+fn enum_init(data: &DataEnum, repr: &Repr) -> Result<(Vec<TokenStream>, Vec<TokenStream>)> {
+    let int = repr.int.as_ref().unwrap();
+    let tags = enum_tags(data, int);
+    let mut default = None;
+    for (i, variant) in data.variants.iter().enumerate() {
+        if is_default_variant(variant)? && default.replace(i).is_some() {
+            return Err(Error::new_spanned(variant, "more than one default variant"));
+        }
+    }
+    let default = default.unwrap_or(0);
+    let variant = &data.variants[default];
+    let tag = &tags[default];
+
+    let mut zeroed = vec![quote! { #tag == 0 }];
+    let mut inits = vec![quote! { ptr.cast::<#int>().write(#tag); }];
+    for (field, (at, offset)) in variant.fields.iter().zip(field_offsets(data, repr, variant)) {
+        let ty = &field.ty;
+        let init = parse_field_init(field)?.unwrap_or(FieldInit::InPlace);
+        let place = quote! { ptr.cast::<u8>().add(#at).cast::<#ty>() };
+        let (is_zeroed, init) = field_init(field, init, place, false)?;
+        zeroed.push(is_zeroed);
+        inits.push(quote! {
+            #[allow(unused_variables)]
+            let #at = #offset;
+            #init
+        });
+    }
+    // The offsets are computed in separate blocks, so initialize in one block.
+    Ok((zeroed, vec![quote! { #(#inits)* }]))
+}
+
+/// The offset of each field of `variant` where the enum's layout puts it, as a
+/// name to bind it to and an expression that may refer to the previous field's name.
+fn field_offsets(data: &DataEnum, repr: &Repr, variant: &Variant) -> Vec<(Ident, TokenStream)> {
+    let int = repr.int.as_ref().unwrap();
+    let mut end = if repr.c {
+        // repr(C, int): the tag is followed by a union of every variant's fields.
+        let tys = data
+            .variants
+            .iter()
+            .flat_map(|v| v.fields.iter().map(|f| &f.ty));
+        quote! { size_of::<#int>().next_multiple_of(1 #(.max(align_of::<#tys>()))*) }
+    } else {
+        // repr(int): every variant is a repr(C) struct that starts with the tag.
+        quote! { size_of::<#int>() }
+    };
+    let mut offsets = Vec::new();
+    for (i, field) in variant.fields.iter().enumerate() {
+        let ty = &field.ty;
+        let at = format_ident!("__at_{}", i);
+        offsets.push((
+            at.clone(),
+            quote! { (#end).next_multiple_of(align_of::<#ty>()) },
+        ));
+        end = quote! { #at + size_of::<#ty>() };
+    }
+    offsets
+}
+
+fn is_default_variant(variant: &Variant) -> Result<bool> {
+    let mut default = false;
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("shm"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `default`"))
+            }
+        })?;
+    }
+    Ok(default)
+}
+
+/// The tag of each variant as an expression of type `int`.
+fn enum_tags(data: &DataEnum, int: &Ident) -> Vec<TokenStream> {
+    let mut tags: Vec<TokenStream> = Vec::new();
+    for variant in &data.variants {
+        let tag = match (&variant.discriminant, tags.last()) {
+            (Some((_, expr)), _) => quote! { ((#expr) as #int) },
+            (None, Some(prev)) => quote! { (#prev + 1) },
+            (None, None) => quote! { (0 as #int) },
+        };
+        tags.push(tag);
     }
+    tags
 }
 
-#[proc_macro_derive(FromShm)]
+fn to_shm_impl() -> TokenStream {
+    let place = place_impl(quote! { <Self as shmoo::ShmInit>::CAPACITY }, true);
+    quote! {
+        #place
+        unsafe {
+            <Self as shmoo::InitInPlace>::init_in_place(ptr)?;
+            Ok(&mut *ptr)
+        }
+    }
+}
+
+/// Binds `ptr` to the first offset from the cursor that is aligned for `Self` and
+/// has room for `size` bytes.
+fn place_impl(size: TokenStream, is_mut: bool) -> TokenStream {
+    let const_tok = if is_mut {
+        quote! { mut }
+    } else {
+        quote! { const }
+    };
+    quote! {
+        let ptr = shm.place::<Self>(#size)?.as_ptr() as *#const_tok Self;
+    }
+}
+
+#[proc_macro_derive(FromShm, attributes(shm))]
 pub fn derive_from_shm(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    match expand_from_shm(input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn expand_from_shm(input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
 
-    let name = input.ident;
+    let repr = check_repr_c(&input, "FromShm")?;
 
-    let generics = add_trait_bounds(input.generics);
+    // Fields of a type parameter's type must be `ShmSafe` and are checked with
+    // `ValidateInPlace`.
+    let generics = add_trait_bounds(input.generics.clone(), parse_quote!(shmoo::ShmSafe));
+    let generics = add_trait_bounds(generics, parse_quote!(shmoo::ValidateInPlace));
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let err = check_repr_c(&input.attrs, &name.span(), "FromShm");
-    if err.is_err() {
-        return err.err().unwrap().into_compile_error().into();
-    }
+    let assert_fields = assert_fields_shm_safe(&input);
+    let validate_in_place = validate_in_place_impl(&input.data, &repr);
+    let from_shm = from_shm_impl(false);
+    let from_shm_mut = from_shm_impl(true);
+
+    Ok(quote! {
+        #assert_fields
 
-    let from_shm = from_shm_impl(&input.data, false);
-    let from_shm_mut = from_shm_impl(&input.data, true);
+        unsafe impl #impl_generics shmoo::ValidateInPlace for #name #ty_generics #where_clause {
+            #validate_in_place
+        }
 
-    let expanded = quote! {
         unsafe impl #impl_generics shmoo::FromShm for #name #ty_generics #where_clause {
             fn from_shm(shm: &shmoo::Shm) -> shmoo::error::Result<&Self> {
                 #from_shm
@@ -88,65 +457,174 @@ pub fn derive_from_shm(input: proc_macro::TokenStream) -> proc_macro::TokenStrea
                 #from_shm_mut
             }
         }
+    })
+}
+
+fn from_shm_impl(is_mut: bool) -> TokenStream {
+    let mut_tok = if is_mut {
+        quote! { mut }
+    } else {
+        quote! {}
     };
+    let place = place_impl(quote! { size_of::<Self>() }, is_mut);
+    quote! {
+        #place
+        unsafe {
+            <Self as shmoo::ValidateInPlace>::validate_in_place(ptr)?;
+            Ok(&#mut_tok *ptr)
+        }
+    }
+}
 
-    proc_macro::TokenStream::from(expanded)
+/// Checks each field of a struct, or the tag of an enum and then the fields of the
+/// variant it selects. Any bytes are a valid union, so its fields are not checked.
+fn validate_in_place_impl(data: &Data, repr: &Repr) -> TokenStream {
+    let (always_valid, checks) = match *data {
+        Data::Struct(ref data) => {
+            let tys: Vec<_> = data.fields.iter().map(|field| &field.ty).collect();
+            let checks = data.fields.members().zip(&tys).map(|(member, ty)| {
+                quote! {
+                    <#ty as shmoo::ValidateInPlace>::validate_in_place(
+                        ::core::ptr::addr_of!((*ptr).#member),
+                    )?;
+                }
+            });
+            (
+                quote! { true #(&& <#tys as shmoo::ValidateInPlace>::ALWAYS_VALID)* },
+                checks.collect(),
+            )
+        }
+        Data::Enum(ref data) => (quote! { false }, vec![enum_validate(data, repr)]),
+        Data::Union(_) => (quote! { true }, Vec::new()),
+    };
+    let ptr = if checks.is_empty() {
+        quote! { _ptr }
+    } else {
+        quote! { ptr }
+    };
+    quote! {
+        const ALWAYS_VALID: bool = #always_valid;
+
+        unsafe fn validate_in_place(#ptr: *const Self) -> shmoo::error::Result<()> {
+            #(
+                unsafe {
+                    #checks
+                }
+            )*
+            Ok(())
+        }
+    }
 }
 
-fn from_shm_impl(data: &Data, is_mut: bool) -> TokenStream {
-    match *data {
-        Data::Struct(ref data) => match data.fields {
-            Fields::Named(_) | Fields::Unnamed(_) | Fields::Unit => {
-                let (mut_tok, const_tok, method) = if is_mut {
-                    (quote! { mut }, quote! { mut }, quote! {as_mut_ptr})
-                } else {
-                    (quote! {}, quote! { const }, quote! {as_ptr})
-                };
+/// Checks the tag, which may be unaligned in a packed struct, and then the fields of
+/// the variant it selects.
+fn enum_validate(data: &DataEnum, repr: &Repr) -> TokenStream {
+    let int = repr.int.as_ref().unwrap();
+    let tags = enum_tags(data, int);
+    let variants = data.variants.iter().map(|variant| {
+        let checks = variant
+            .fields
+            .iter()
+            .zip(field_offsets(data, repr, variant))
+            .map(|(field, (at, offset))| {
+                let ty = &field.ty;
                 quote! {
-                    let size = size_of::<Self>();
-                    if shm.len() < size {
-                        return Err(shmoo::error::Error::new(shmoo::error::ErrorKind::SizeError(shm.len())));
-                    }
-                    let ptr = shm[..size].#method() as *#const_tok Self;
-                    if !ptr.is_aligned() {
-                        return Err(shmoo::error::Error::new(shmoo::error::ErrorKind::AlignmentError(align_of::<Self>())));
-                    }
-                    unsafe { Ok(&#mut_tok *ptr) }
+                    let #at = #offset;
+                    <#ty as shmoo::ValidateInPlace>::validate_in_place(
+                        ptr.cast::<u8>().add(#at).cast::<#ty>(),
+                    )?;
                 }
-            }
-        },
-        Data::Enum(_) => unimplemented!(),
-        Data::Union(_) => unimplemented!(),
+            });
+        quote! { #(#checks)* }
+    });
+    quote! {
+        let tag = ptr.cast::<#int>().read_unaligned();
+        #(
+            if tag == #tags {
+                #variants
+            } else
+        )* {
+            return Err(shmoo::error::Error::new(shmoo::error::ErrorKind::DiscriminantError(tag as i128)));
+        }
     }
 }
 
-fn add_trait_bounds(mut generics: Generics) -> Generics {
+fn add_trait_bounds(mut generics: Generics, bound: TypeParamBound) -> Generics {
     for param in &mut generics.params {
         if let GenericParam::Type(ref mut type_param) = *param {
-            type_param.bounds.push(parse_quote!(shmoo::ToShm));
+            type_param.bounds.push(bound.clone());
         }
     }
     generics
 }
 
-fn check_repr_c(attrs: &[Attribute], span: &Span, trait_name: &str) -> Result<()> {
-    let mut has_repr = false;
-    let err_msg = &format!("{}: struct must be repr(C)", trait_name);
-    for attr in attrs {
+/// The parts of a `#[repr(...)]` the derives care about.
+struct Repr {
+    c: bool,
+    transparent: bool,
+    packed: bool,
+    /// The integer type of an enum's tag.
+    int: Option<Ident>,
+}
+
+const INTS: [&str; 10] = [
+    "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize",
+];
+
+/// Structs must be `repr(C)` or `repr(transparent)`, unions `repr(C)`, and enums need
+/// an integer tag, with or without `C`, so that their layout is defined. `align(N)`
+/// and `packed` may be added to any of them.
+fn check_repr_c(input: &DeriveInput, trait_name: &str) -> Result<Repr> {
+    let err_msg = &format!(
+        "{}: {}",
+        trait_name,
+        match input.data {
+            Data::Struct(_) => "struct must be repr(C) or repr(transparent)",
+            Data::Union(_) => "union must be repr(C)",
+            Data::Enum(_) => "enum must be repr(u8), repr(C, u8) or another integer type",
+        }
+    );
+    let mut repr = Repr {
+        c: false,
+        transparent: false,
+        packed: false,
+        int: None,
+    };
+    for attr in &input.attrs {
         if attr.path().is_ident("repr") {
-            has_repr = true;
             attr.parse_nested_meta(|meta| {
                 if meta.path.is_ident("C") {
-                    Ok(())
+                    repr.c = true;
+                } else if meta.path.is_ident("transparent") {
+                    repr.transparent = true;
+                } else if INTS.iter().any(|int| meta.path.is_ident(int)) {
+                    repr.int = meta.path.get_ident().cloned();
+                } else if meta.path.is_ident("align") {
+                    let content;
+                    parenthesized!(content in meta.input);
+                    content.parse::<LitInt>()?;
+                } else if meta.path.is_ident("packed") {
+                    repr.packed = true;
+                    if meta.input.peek(token::Paren) {
+                        let content;
+                        parenthesized!(content in meta.input);
+                        content.parse::<LitInt>()?;
+                    }
                 } else {
-                    Err(meta.error(err_msg))
+                    return Err(meta.error(err_msg));
                 }
+                Ok(())
             })?;
         }
     }
-    if has_repr {
-        Ok(())
+    let valid = match input.data {
+        Data::Struct(_) => (repr.c || repr.transparent) && repr.int.is_none(),
+        Data::Union(_) => repr.c && repr.int.is_none(),
+        Data::Enum(_) => repr.int.is_some(),
+    };
+    if valid {
+        Ok(repr)
     } else {
-        Err(Error::new(*span, err_msg))
+        Err(Error::new(input.ident.span(), err_msg))
     }
 }
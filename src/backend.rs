@@ -0,0 +1,531 @@
+//! Where a [`Shm`](crate::Shm)'s memory comes from.
+//!
+//! [`OpenOptions::map_backend`](crate::OpenOptions::map_backend) and
+//! [`open_backend`](crate::OpenOptions::open_backend) map a segment through any
+//! [`Backend`]; the other `OpenOptions` methods are shorthands for the backends here.
+
+use std::num::NonZero;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::ptr::{self, NonNull};
+
+use nix::errno::Errno;
+use nix::libc::{self, c_void, off_t};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::mman::{
+    mmap, mmap_anonymous, mremap, munmap, shm_open, shm_unlink, MRemapFlags, MapFlags, ProtFlags,
+};
+use nix::sys::stat::{fstat, Mode};
+use nix::sys::statfs::{fstatfs, HUGETLBFS_MAGIC};
+use nix::unistd::{ftruncate, unlink};
+use nix::{fcntl, fcntl::OFlag};
+
+use crate::error::Result;
+
+/// Creates, opens, maps and removes the memory behind a [`Shm`](crate::Shm).
+///
+/// Sizes are in bytes and include the segment's header. The provided methods work
+/// on the descriptor returned by [`fd`](Backend::fd), so descriptor-based backends
+/// only need to implement [`create`](Backend::create), [`open`](Backend::open) and
+/// [`fd`](Backend::fd).
+pub trait Backend {
+    /// Creates the segment, or opens it if `oflg` allows, and sizes it to `size`.
+    fn create(&mut self, size: usize, oflg: OFlag, mode: Mode) -> Result<()>;
+
+    /// Opens an existing segment.
+    fn open(&mut self, oflg: OFlag, mode: Mode) -> Result<()>;
+
+    /// The size of the opened segment.
+    fn size(&self) -> Result<usize> {
+        let statbuf = fstat(self.fd().ok_or(Errno::EBADF)?.as_raw_fd())?;
+        Ok(statbuf.st_size as usize)
+    }
+
+    /// The granularity of the segment's size and mappings: the huge page size if the
+    /// descriptor is on hugetlbfs, and 1 otherwise. Sizes passed to the provided
+    /// methods are rounded up to it.
+    fn page_size(&self) -> Result<usize> {
+        let Some(fd) = self.fd() else {
+            return Ok(1);
+        };
+        let statfs = fstatfs(fd)?;
+        if statfs.filesystem_type() == HUGETLBFS_MAGIC {
+            Ok(statfs.block_size() as usize)
+        } else {
+            Ok(1)
+        }
+    }
+
+    /// Grows or shrinks the segment to `size`, without touching existing mappings.
+    fn truncate(&mut self, size: usize) -> Result<()> {
+        let size = size.next_multiple_of(self.page_size()?);
+        ftruncate(self.fd().ok_or(Errno::EBADF)?, size as i64)?;
+        Ok(())
+    }
+
+    /// Maps `size` bytes of the segment into this process.
+    fn map(
+        &mut self,
+        size: usize,
+        prot: ProtFlags,
+        flgs: MapFlags,
+        offset: off_t,
+    ) -> Result<NonNull<c_void>> {
+        let size = size.next_multiple_of(self.page_size()?);
+        let fd = self.fd().ok_or(Errno::EBADF)?;
+        let size = NonZero::new(size).ok_or(Errno::EINVAL)?;
+        Ok(unsafe { mmap(None, size, prot, flgs, fd, offset)? })
+    }
+
+    /// Resizes a mapping returned by [`map`](Backend::map), possibly moving it.
+    fn remap(&mut self, ptr: NonNull<c_void>, old: usize, new: usize) -> Result<NonNull<c_void>> {
+        let page = self.page_size()?;
+        let (old, new) = (old.next_multiple_of(page), new.next_multiple_of(page));
+        Ok(unsafe { mremap(ptr, old, new, MRemapFlags::MREMAP_MAYMOVE, None)? })
+    }
+
+    /// Removes a mapping returned by [`map`](Backend::map).
+    fn unmap(&mut self, ptr: NonNull<c_void>, size: usize) -> Result<()> {
+        let size = size.next_multiple_of(self.page_size()?);
+        unsafe { munmap(ptr, size)? };
+        Ok(())
+    }
+
+    /// Called when the [`Shm`](crate::Shm) is dropped. Backends with a name remove
+    /// it here so that the segment is freed once every process has unmapped it.
+    fn unlink(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The descriptor backing the segment, if it has one.
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+}
+
+/// A named POSIX segment from `shm_open`.
+pub struct PosixShm {
+    name: String,
+    fd: Option<OwnedFd>,
+}
+
+impl PosixShm {
+    pub fn new(name: &str) -> Self {
+        let name = if name.starts_with('/') {
+            String::from(name)
+        } else {
+            String::from("/") + name
+        };
+        PosixShm { name, fd: None }
+    }
+}
+
+impl Backend for PosixShm {
+    fn create(&mut self, size: usize, oflg: OFlag, mode: Mode) -> Result<()> {
+        self.open(oflg, mode)?;
+        self.truncate(size)
+    }
+
+    fn open(&mut self, oflg: OFlag, mode: Mode) -> Result<()> {
+        self.fd = Some(shm_open(self.name.as_str(), oflg, mode)?);
+        Ok(())
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        // Ignore ENOENT in case another process already closed the file.
+        match shm_unlink(self.name.as_str()) {
+            Err(Errno::ENOENT) => Ok(()),
+            r => Ok(r?),
+        }
+    }
+
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.fd.as_ref().map(|fd| fd.as_fd())
+    }
+}
+
+/// An unnamed segment from `memfd_create`, freed once every descriptor and mapping
+/// of it is closed. It can be sealed.
+#[derive(Default)]
+pub struct Memfd {
+    fd: Option<OwnedFd>,
+    huge: Option<HugePageSize>,
+}
+
+impl Memfd {
+    pub fn new() -> Self {
+        Memfd::default()
+    }
+
+    /// Backs the segment with huge pages from the kernel's reserved pool.
+    pub fn huge_pages(mut self, size: HugePageSize) -> Self {
+        self.huge = Some(size);
+        self
+    }
+}
+
+impl Backend for Memfd {
+    fn create(&mut self, size: usize, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        let mut flags = MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING;
+        if let Some(huge) = self.huge {
+            flags |= huge.memfd_flags();
+        }
+        self.fd = Some(memfd_create(c"shmoo", flags)?);
+        self.truncate(size)
+    }
+
+    /// A memfd has no name to open it by.
+    fn open(&mut self, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        Err(Errno::ENOENT.into())
+    }
+
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.fd.as_ref().map(|fd| fd.as_fd())
+    }
+}
+
+/// A descriptor that is already open, e.g. one received from or inherited from
+/// another process.
+pub struct Fd(OwnedFd);
+
+impl Fd {
+    pub fn new(fd: OwnedFd) -> Self {
+        Fd(fd)
+    }
+}
+
+impl Backend for Fd {
+    fn create(&mut self, size: usize, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        self.truncate(size)
+    }
+
+    fn open(&mut self, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        Ok(())
+    }
+
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.0.as_fd())
+    }
+}
+
+/// A regular file, whose contents outlive the process. It is never removed.
+pub struct File {
+    path: PathBuf,
+    fd: Option<OwnedFd>,
+}
+
+impl File {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        File {
+            path: path.into(),
+            fd: None,
+        }
+    }
+}
+
+impl Backend for File {
+    fn create(&mut self, size: usize, oflg: OFlag, mode: Mode) -> Result<()> {
+        self.open(oflg, mode)?;
+        self.truncate(size)
+    }
+
+    fn open(&mut self, oflg: OFlag, mode: Mode) -> Result<()> {
+        let fd = fcntl::open(&self.path, oflg | OFlag::O_CLOEXEC, mode)?;
+        self.fd = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok(())
+    }
+
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.fd.as_ref().map(|fd| fd.as_fd())
+    }
+}
+
+/// A file on a hugetlbfs mount, e.g. `/dev/hugepages`, so that the segment is backed
+/// by huge pages. Sizes are rounded up to the mount's page size, and the file is
+/// removed on drop like a POSIX segment since its pages are never swapped out.
+pub struct HugeTlbFs {
+    file: File,
+}
+
+impl HugeTlbFs {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        HugeTlbFs {
+            file: File::new(path),
+        }
+    }
+}
+
+impl Backend for HugeTlbFs {
+    fn create(&mut self, size: usize, oflg: OFlag, mode: Mode) -> Result<()> {
+        self.open(oflg, mode)?;
+        self.truncate(size)
+    }
+
+    /// Fails with `EINVAL` if the file is not on a hugetlbfs mount.
+    fn open(&mut self, oflg: OFlag, mode: Mode) -> Result<()> {
+        self.file.open(oflg, mode)?;
+        if self.page_size()? == 1 {
+            return Err(Errno::EINVAL.into());
+        }
+        Ok(())
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        match unlink(&self.file.path) {
+            Err(Errno::ENOENT) => Ok(()),
+            r => Ok(r?),
+        }
+    }
+
+    fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.file.fd()
+    }
+}
+
+/// A System V segment from `shmget`, attached with `shmat`, for peers that predate
+/// POSIX shared memory. It has no descriptor, so it cannot be sent, sealed or
+/// resized, and the mapping offset must be zero.
+///
+/// Like every segment, it starts with the [`Shm`](crate::Shm)'s header: three
+/// native-endian words holding the length of the data after it, the offset of the
+/// first free byte and a generation counter, 24 bytes in all on 64-bit targets.
+/// Peers that do not use this crate must leave the header alone and keep their data
+/// after it. Creating a segment always fails with `EEXIST` if the key is taken, so
+/// that a peer's segment is never overwritten; attach to it with
+/// [`open_backend`](crate::OpenOptions::open_backend) instead. Only the process
+/// that created the segment removes it.
+pub struct SysV {
+    key: libc::key_t,
+    id: Option<libc::c_int>,
+    /// Whether this process created the segment, and so may remove it.
+    created: bool,
+}
+
+impl SysV {
+    /// A segment identified by `key`, e.g. from `ftok`, or `IPC_PRIVATE` for a new
+    /// segment that only this process and its children know the id of.
+    pub fn new(key: libc::key_t) -> Self {
+        SysV {
+            key,
+            id: None,
+            created: false,
+        }
+    }
+
+    /// A segment that is already identified by its `shmid`.
+    pub fn from_id(id: libc::c_int) -> Self {
+        SysV {
+            key: libc::IPC_PRIVATE,
+            id: Some(id),
+            created: false,
+        }
+    }
+
+    /// The segment's `shmid`, once it is created or opened.
+    pub fn id(&self) -> Option<libc::c_int> {
+        self.id
+    }
+
+    fn get(&mut self, size: usize, flags: libc::c_int) -> Result<()> {
+        let id = unsafe { libc::shmget(self.key, size, flags) };
+        self.id = Some(Errno::result(id)?);
+        Ok(())
+    }
+
+    fn stat(&self) -> Result<libc::shmid_ds> {
+        let id = self.id.ok_or(Errno::EBADF)?;
+        let mut ds = unsafe { std::mem::zeroed() };
+        Errno::result(unsafe { libc::shmctl(id, libc::IPC_STAT, &mut ds) })?;
+        Ok(ds)
+    }
+}
+
+impl Backend for SysV {
+    /// Fails with `EEXIST` if a segment with the key exists, even without `O_EXCL`,
+    /// since resetting its header would overwrite a peer's data.
+    fn create(&mut self, size: usize, _oflg: OFlag, mode: Mode) -> Result<()> {
+        let flags = mode.bits() as libc::c_int | libc::IPC_CREAT | libc::IPC_EXCL;
+        self.get(size, flags)?;
+        self.created = true;
+        Ok(())
+    }
+
+    fn open(&mut self, _oflg: OFlag, mode: Mode) -> Result<()> {
+        if self.id.is_none() {
+            self.get(0, mode.bits() as libc::c_int)?;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<usize> {
+        Ok(self.stat()?.shm_segsz)
+    }
+
+    /// System V segments have a fixed size.
+    fn truncate(&mut self, _size: usize) -> Result<()> {
+        Err(Errno::ENOTSUP.into())
+    }
+
+    fn map(
+        &mut self,
+        _size: usize,
+        prot: ProtFlags,
+        _flgs: MapFlags,
+        offset: off_t,
+    ) -> Result<NonNull<c_void>> {
+        let id = self.id.ok_or(Errno::EBADF)?;
+        if offset != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut flags = 0;
+        if !prot.contains(ProtFlags::PROT_WRITE) {
+            flags |= libc::SHM_RDONLY;
+        }
+        if prot.contains(ProtFlags::PROT_EXEC) {
+            flags |= libc::SHM_EXEC;
+        }
+        let ptr = unsafe { libc::shmat(id, ptr::null(), flags) };
+        if ptr == libc::MAP_FAILED {
+            return Err(Errno::last().into());
+        }
+        Ok(NonNull::new(ptr).ok_or(Errno::EINVAL)?)
+    }
+
+    fn remap(
+        &mut self,
+        _ptr: NonNull<c_void>,
+        _old: usize,
+        _new: usize,
+    ) -> Result<NonNull<c_void>> {
+        Err(Errno::ENOTSUP.into())
+    }
+
+    fn unmap(&mut self, ptr: NonNull<c_void>, _size: usize) -> Result<()> {
+        Errno::result(unsafe { libc::shmdt(ptr.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Marks the segment for removal once the last process detaches, if this process
+    /// created it. A process can no longer look it up by key afterwards.
+    fn unlink(&mut self) -> Result<()> {
+        let (Some(id), true) = (self.id, self.created) else {
+            return Ok(());
+        };
+        // Ignore EINVAL and EIDRM in case another process already removed it.
+        match Errno::result(unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) }) {
+            Err(Errno::EINVAL | Errno::EIDRM) => Ok(()),
+            r => Ok(r.map(drop)?),
+        }
+    }
+}
+
+/// A `MAP_SHARED | MAP_ANONYMOUS` mapping. It has no descriptor, so it can only be
+/// shared with children created by `fork`, which inherit the mapping.
+#[derive(Default)]
+pub struct Anonymous {
+    size: usize,
+    huge: Option<HugePageSize>,
+}
+
+impl Anonymous {
+    pub fn new() -> Self {
+        Anonymous::default()
+    }
+
+    /// Backs the mapping with huge pages from the kernel's reserved pool.
+    pub fn huge_pages(mut self, size: HugePageSize) -> Self {
+        self.huge = Some(size);
+        self
+    }
+}
+
+impl Backend for Anonymous {
+    fn create(&mut self, size: usize, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        self.size = size;
+        Ok(())
+    }
+
+    /// An anonymous mapping cannot be opened again.
+    fn open(&mut self, _oflg: OFlag, _mode: Mode) -> Result<()> {
+        Err(Errno::ENOENT.into())
+    }
+
+    fn size(&self) -> Result<usize> {
+        Ok(self.size)
+    }
+
+    fn page_size(&self) -> Result<usize> {
+        self.huge.map_or(Ok(1), HugePageSize::bytes)
+    }
+
+    /// Children would not see the new size.
+    fn truncate(&mut self, _size: usize) -> Result<()> {
+        Err(Errno::ENOTSUP.into())
+    }
+
+    fn map(
+        &mut self,
+        size: usize,
+        prot: ProtFlags,
+        mut flgs: MapFlags,
+        _offset: off_t,
+    ) -> Result<NonNull<c_void>> {
+        if let Some(huge) = self.huge {
+            flgs |= huge.map_flags();
+        }
+        let size = size.next_multiple_of(self.page_size()?);
+        let size = NonZero::new(size).ok_or(Errno::EINVAL)?;
+        Ok(unsafe { mmap_anonymous(None, size, prot, flgs | MapFlags::MAP_SHARED)? })
+    }
+}
+
+/// The size of the huge pages backing a segment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// The system's default huge page size.
+    #[default]
+    Default,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl HugePageSize {
+    /// The page size in bytes. The default is read from `/proc/meminfo`.
+    pub fn bytes(self) -> Result<usize> {
+        match self {
+            HugePageSize::Size2MiB => Ok(2 << 20),
+            HugePageSize::Size1GiB => Ok(1 << 30),
+            HugePageSize::Default => {
+                let meminfo = std::fs::read_to_string("/proc/meminfo")?;
+                meminfo
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Hugepagesize:"))
+                    .and_then(|kb| {
+                        kb.trim()
+                            .trim_end_matches("kB")
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                    })
+                    .map(|kb| kb << 10)
+                    .ok_or(Errno::ENOTSUP.into())
+            }
+        }
+    }
+
+    pub(crate) fn map_flags(self) -> MapFlags {
+        match self {
+            HugePageSize::Default => MapFlags::MAP_HUGETLB,
+            HugePageSize::Size2MiB => MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_2MB,
+            HugePageSize::Size1GiB => MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_1GB,
+        }
+    }
+
+    fn memfd_flags(self) -> MemFdCreateFlag {
+        match self {
+            HugePageSize::Default => MemFdCreateFlag::MFD_HUGETLB,
+            HugePageSize::Size2MiB => MemFdCreateFlag::MFD_HUGETLB | MemFdCreateFlag::MFD_HUGE_2MB,
+            HugePageSize::Size1GiB => MemFdCreateFlag::MFD_HUGETLB | MemFdCreateFlag::MFD_HUGE_1GB,
+        }
+    }
+}
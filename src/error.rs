@@ -1,6 +1,9 @@
 use std::fmt::{Debug, Display};
 use std::io;
 
+use nix::errno::Errno;
+use nix::fcntl::SealFlag;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct Error {
@@ -11,12 +14,37 @@ pub enum ErrorKind {
     SizeError(usize),
     AlignmentError(usize),
     IoError(io::Error),
+    /// A pthread call failed; holds the name of the call and the errno it returned.
+    SyncError(&'static str, Errno),
+    /// A peer sent a different number of file descriptors than expected.
+    FdCountError(usize),
+    /// A segment's header does not describe the segment, e.g. because it was not
+    /// created by this crate or was truncated.
+    HeaderError,
+    /// A segment is missing the contained seals.
+    SealError(SealFlag),
+    /// The kernel refused a mapping option; holds the name of the option and the
+    /// errno it returned.
+    MapError(&'static str, Errno),
+    /// An enum read from a segment has a tag that matches none of its variants.
+    DiscriminantError(i128),
+    /// A value read from a segment is not valid for its type; holds the name of the
+    /// type, e.g. a `bool` that is neither 0 nor 1.
+    ValueError(&'static str),
+    /// A [`MsgQueue`](crate::queue::MsgQueue) has no message to receive.
+    QueueEmpty,
+    /// A [`MsgQueue`](crate::queue::MsgQueue) has no room for another message.
+    QueueFull,
 }
 
 impl Error {
     pub fn new(kind: ErrorKind) -> Self {
         Self { kind }
     }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl std::error::Error for Error {}
@@ -32,6 +60,20 @@ impl Display for Error {
                 format!("alignment of object must have an alignment of {}", align)
             }
             ErrorKind::IoError(err) => format!("io error: {}", err),
+            ErrorKind::SyncError(op, errno) => format!("{} failed: {}", op, errno),
+            ErrorKind::HeaderError => String::from("segment header is invalid"),
+            ErrorKind::SealError(seals) => format!("segment is missing seals: {:?}", seals),
+            ErrorKind::MapError(option, errno) => format!("{} was refused: {}", option, errno),
+            ErrorKind::DiscriminantError(tag) => format!("invalid enum discriminant: {}", tag),
+            ErrorKind::ValueError(ty) => format!("invalid value for {}", ty),
+            ErrorKind::QueueEmpty => String::from("queue is empty"),
+            ErrorKind::QueueFull => String::from("queue is full"),
+            ErrorKind::FdCountError(count) => {
+                format!(
+                    "received an unexpected number of file descriptors: {}",
+                    count
+                )
+            }
         };
         write!(f, "{}", msg)
     }
@@ -54,3 +96,13 @@ impl From<nix::Error> for Error {
         Error::new(ErrorKind::IoError(value.into()))
     }
 }
+
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        match value.kind {
+            ErrorKind::IoError(err) => err,
+            ErrorKind::SyncError(_, errno) | ErrorKind::MapError(_, errno) => errno.into(),
+            _ => io::Error::other(value),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use nix::unistd::{read, write};
+#[cfg(feature = "async")]
+use tokio::io::unix::AsyncFd;
+
+use crate::error::Result;
+use crate::fd::{recv_fds, send_fds};
+use crate::{InitInPlace, ShmSafe, ValidateInPlace};
+
+/// The shared memory half of a cross-process wakeup. A consumer [`arm`](Self::arm)s
+/// the flag before it goes to sleep, and a producer only signals the consumer's
+/// [`EventNotifier`] if the flag was armed, so an eventfd write is only paid for when
+/// the consumer is actually waiting.
+#[repr(transparent)]
+pub struct EventFlag {
+    armed: AtomicU32,
+}
+
+impl EventFlag {
+    pub fn new() -> Self {
+        let armed = AtomicU32::new(0);
+        EventFlag { armed }
+    }
+
+    /// Announces that the consumer is about to wait. The consumer must check its
+    /// condition (e.g. that the queue is still empty) again after arming, since a
+    /// producer may have made progress in between.
+    pub fn arm(&self) {
+        self.armed.store(1, Ordering::SeqCst);
+    }
+
+    /// Withdraws an [`arm`](Self::arm), returning whether it was still pending.
+    pub fn disarm(&self) -> bool {
+        self.armed.swap(0, Ordering::SeqCst) == 1
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::SeqCst) == 1
+    }
+}
+
+impl Default for EventFlag {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl ShmSafe for EventFlag {}
+
+// All zero bytes is a disarmed flag.
+unsafe impl InitInPlace for EventFlag {
+    const ZEROED: bool = true;
+
+    unsafe fn init_in_place(_ptr: *mut Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl ValidateInPlace for EventFlag {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The process local half of a cross-process wakeup, backed by a nonblocking eventfd.
+///
+/// The consumer creates the notifier, registers it with its event loop (it implements
+/// [`AsFd`]), and hands a copy to producers with [`send`](Self::send). Producers call
+/// [`notify`](Self::notify) after making progress, e.g. pushing onto a queue.
+pub struct EventNotifier {
+    fd: OwnedFd,
+}
+
+impl EventNotifier {
+    pub fn new() -> Result<Self> {
+        let flags = EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK;
+        let fd = EventFd::from_value_and_flags(0, flags)?.into();
+        Ok(EventNotifier { fd })
+    }
+
+    /// Sends a duplicate of the eventfd to the peer of `stream`.
+    pub fn send(&self, stream: &UnixStream) -> Result<()> {
+        send_fds(stream, &[self.fd.as_fd()], &[0])
+    }
+
+    /// Receives an eventfd sent by [`send`](Self::send).
+    pub fn recv(stream: &UnixStream) -> Result<Self> {
+        let [fd] = recv_fds::<1>(stream, &mut [0])?;
+        Ok(EventNotifier { fd })
+    }
+
+    /// Wakes the consumer if it armed `flag`. Returns whether a wakeup was sent.
+    pub fn notify(&self, flag: &EventFlag) -> Result<bool> {
+        if !flag.disarm() {
+            return Ok(false);
+        }
+        match write(&self.fd, &1u64.to_ne_bytes()) {
+            // The counter is saturated, so the consumer will wake up anyway.
+            Ok(_) | Err(Errno::EAGAIN) => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Resets the eventfd after a wakeup so the event loop stops reporting it as
+    /// readable. Returns the number of wakeups since the last call.
+    pub fn clear(&self) -> Result<u64> {
+        let mut buf = [0; size_of::<u64>()];
+        match read(self.fd.as_raw_fd(), &mut buf) {
+            Ok(_) => Ok(u64::from_ne_bytes(buf)),
+            Err(Errno::EAGAIN) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Calls `ready` until it returns a value, arming `flag` and blocking until a
+    /// producer notifies this eventfd whenever it returns `None`.
+    pub fn wait_until<T>(&self, flag: &EventFlag, ready: impl FnMut() -> Option<T>) -> Result<T> {
+        unsafe { self.wait_until_raw(flag, ready) }
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but for a `flag` inside state that
+    /// `ready` borrows mutably, e.g. a queue header. `flag` is only dereferenced
+    /// between calls to `ready`.
+    ///
+    /// # Safety
+    ///
+    /// `flag` must stay valid until this returns, and `ready` must not keep a
+    /// reference to it after returning.
+    pub(crate) unsafe fn wait_until_raw<T>(
+        &self,
+        flag: *const EventFlag,
+        mut ready: impl FnMut() -> Option<T>,
+    ) -> Result<T> {
+        loop {
+            if let Some(val) = ready() {
+                return Ok(val);
+            }
+            unsafe { (*flag).arm() };
+            // A producer may have made progress before it could see the flag.
+            if let Some(val) = ready() {
+                unsafe { (*flag).disarm() };
+                return Ok(val);
+            }
+            let mut fds = [PollFd::new(self.fd.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, PollTimeout::NONE) {
+                Ok(_) | Err(Errno::EINTR) => (),
+                Err(e) => return Err(e.into()),
+            }
+            self.clear()?;
+        }
+    }
+}
+
+impl AsFd for EventNotifier {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for EventNotifier {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// An [`EventNotifier`] registered with the tokio reactor, so consumers can await a
+/// wakeup instead of blocking the runtime.
+#[cfg(feature = "async")]
+pub struct AsyncEventNotifier {
+    inner: AsyncFd<EventNotifier>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncEventNotifier {
+    /// Registers `notifier` with the current tokio runtime. Panics if called outside
+    /// of a runtime.
+    pub fn new(notifier: EventNotifier) -> Result<Self> {
+        // The notifier owns its eventfd and never replaces it.
+        let inner = unsafe { AsyncFd::register(notifier) }.map_err(std::io::Error::from)?;
+        Ok(AsyncEventNotifier { inner })
+    }
+
+    pub fn get_ref(&self) -> &EventNotifier {
+        self.inner.get_ref()
+    }
+
+    /// Polls `ready` until it returns a value, arming `flag` and awaiting a wakeup
+    /// whenever it returns `None`.
+    pub async fn wait_until<T>(
+        &self,
+        flag: &EventFlag,
+        ready: impl FnMut() -> Option<T>,
+    ) -> Result<T> {
+        unsafe { self.wait_until_raw(flag, ready).await }
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but for a `flag` inside state that
+    /// `ready` borrows mutably, e.g. a queue header. `flag` is only dereferenced
+    /// between calls to `ready`.
+    ///
+    /// # Safety
+    ///
+    /// `flag` must stay valid until the future completes, and `ready` must not keep
+    /// a reference to it after returning.
+    pub(crate) async unsafe fn wait_until_raw<T>(
+        &self,
+        flag: *const EventFlag,
+        mut ready: impl FnMut() -> Option<T>,
+    ) -> Result<T> {
+        loop {
+            if let Some(val) = ready() {
+                return Ok(val);
+            }
+            unsafe { (*flag).arm() };
+            // A producer may have made progress before it could see the flag.
+            if let Some(val) = ready() {
+                unsafe { (*flag).disarm() };
+                return Ok(val);
+            }
+            let mut guard = self.inner.readable().await?;
+            self.inner.get_ref().clear()?;
+            guard.clear_ready();
+        }
+    }
+}
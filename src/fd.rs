@@ -0,0 +1,51 @@
+use std::io::{IoSlice, IoSliceMut};
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use nix::cmsg_space;
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Sends `data` along with duplicates of `fds` to the peer of `stream` as an
+/// `SCM_RIGHTS` control message.
+pub(crate) fn send_fds(stream: &UnixStream, fds: &[BorrowedFd], data: &[u8]) -> Result<()> {
+    let raw: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+    let iov = [IoSlice::new(data)];
+    let cmsgs = [ControlMessage::ScmRights(&raw)];
+    sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+/// Receives exactly `N` file descriptors and fills `data` with the accompanying
+/// message. Fails if the peer sent a different number of descriptors.
+pub(crate) fn recv_fds<const N: usize>(
+    stream: &UnixStream,
+    data: &mut [u8],
+) -> Result<[OwnedFd; N]> {
+    let len = data.len();
+    let mut iov = [IoSliceMut::new(data)];
+    let mut cmsg_buf = cmsg_space!([RawFd; N]);
+    let msg = recvmsg::<()>(
+        stream.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buf),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+
+    let mut fds = Vec::with_capacity(N);
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(raw) = cmsg {
+            // Take ownership immediately so nothing leaks if we bail out below.
+            fds.extend(
+                raw.into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+    if msg.bytes != len || msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+        return Err(Error::new(ErrorKind::SizeError(len)));
+    }
+    fds.try_into()
+        .map_err(|fds: Vec<OwnedFd>| Error::new(ErrorKind::FdCountError(fds.len())))
+}
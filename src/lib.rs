@@ -1,11 +1,25 @@
+mod fd;
 mod shm;
+mod typed;
 
+pub mod backend;
 pub mod error;
+pub mod event;
+pub mod numa;
+pub mod queue;
 pub mod sync;
 
 pub use error::Error;
-pub use shm::Shm;
-pub use shm_derive::{FromShm, ShmInit};
+pub use nix::fcntl::SealFlag;
+pub use shm::{OpenOptions, Shm, ShmCursor, ShmHandle, ShmRef, INHERIT_VAR};
+pub use shm_derive::{FromShm, ShmInit, ShmSafe};
+pub use typed::TypedShm;
+
+use std::marker::PhantomData;
+use std::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32,
+    AtomicU64, AtomicU8, AtomicUsize,
+};
 
 // Allows derive macros to use fully qualified trait names (e.g. 'shmoo::ShmInit')
 // and still compile within this crate.
@@ -15,7 +29,12 @@ extern crate self as shmoo;
 ///
 /// TODO: Implementers must guarantee provenance, size, and alignment are correct. Can
 /// Shm help with that?
-pub unsafe trait ShmInit: Sized + Default {
+pub unsafe trait ShmInit: Sized {
+    /// Bytes the object takes up in the segment, which may exceed its size to leave
+    /// room for data that follows it, like a ring buffer's slots. Set it with
+    /// `#[shm(capacity = ...)]` on the derive.
+    const CAPACITY: usize = size_of::<Self>();
+
     fn shm_init(shm: &mut Shm) -> error::Result<&Self>;
     fn shm_init_mut(shm: &mut Shm) -> error::Result<&mut Self>;
 }
@@ -29,9 +48,238 @@ pub unsafe trait ShmInit: Sized + Default {
 /// and any pointers created to Self have the proper alignment and provenance.
 ///
 /// Use the [`FromShm`](shm_derive::FromShm) derive macro to assert these invariants
-/// at compile time.
+/// at compile time. It also supports `repr(transparent)` structs, `repr(C)` unions
+/// and enums with an integer repr, like `repr(u8)` or `repr(C, u8)`, and checks the
+/// object with [`ValidateInPlace`] before handing it out, so that e.g. an enum whose
+/// tag matches none of its variants is rejected. Any of these may be over-aligned
+/// with `align(N)`, in which case the object is read at the next aligned offset, or
+/// `packed`, in which case it is read wherever the cursor is.
 ///
 pub unsafe trait FromShm: Sized {
     fn from_shm(shm: &Shm) -> error::Result<&Self>;
     fn from_shm_mut(shm: &mut Shm) -> error::Result<&mut Self>;
 }
+
+/// A type that can be shared between processes through a segment: it holds no
+/// pointers, references or descriptors, which are only meaningful in the process
+/// that made them, and has a defined layout.
+///
+/// The [`ShmInit`](shm_derive::ShmInit) and [`FromShm`](shm_derive::FromShm)
+/// derives require every field to be `ShmSafe`. Derive it with
+/// [`ShmSafe`](shm_derive::ShmSafe) for structs that are nested in others.
+///
+/// # Safety
+///
+/// `Self` must not contain pointers, references or descriptors, and must be usable
+/// from several processes at once through the same memory.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be shared between processes",
+    label = "`{Self}` is not `ShmSafe`",
+    note = "pointers, references and descriptors are only valid in the process that made them"
+)]
+pub unsafe trait ShmSafe {}
+
+/// Implements [`ShmSafe`] for types that hold no pointers.
+macro_rules! shm_safe {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            unsafe impl ShmSafe for $ty {}
+        )*
+    };
+}
+
+shm_safe!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char,);
+shm_safe!(
+    AtomicBool,
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicUsize,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicIsize,
+);
+
+unsafe impl<T: ?Sized> ShmSafe for PhantomData<T> {}
+
+unsafe impl<T: ShmSafe, const N: usize> ShmSafe for [T; N] {}
+
+/// Initializes a value directly in shared memory, so that it is never built on the
+/// stack. The [`ShmInit`](shm_derive::ShmInit) derive zero-fills a struct and then
+/// initializes each of its fields with this trait. Fields of a packed struct may be
+/// unaligned, so the derive only zero-fills them and rejects types that are not
+/// [`ZEROED`](Self::ZEROED).
+///
+/// # Safety
+///
+/// [`init_in_place`](Self::init_in_place) must leave a valid `Self` behind, and
+/// [`ZEROED`](Self::ZEROED) may only be true if all zero bytes are a valid `Self`.
+pub unsafe trait InitInPlace {
+    /// Whether zeroed memory already holds a valid, initialized `Self`, in which case
+    /// [`init_in_place`](Self::init_in_place) does nothing.
+    const ZEROED: bool = false;
+
+    /// Initializes the `Self` at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes, properly aligned, and point to zeroed memory.
+    unsafe fn init_in_place(ptr: *mut Self) -> error::Result<()>;
+}
+
+/// Implements [`InitInPlace`] for types whose zero bytes are a valid value.
+macro_rules! init_zeroed {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            unsafe impl InitInPlace for $ty {
+                const ZEROED: bool = true;
+
+                unsafe fn init_in_place(_ptr: *mut Self) -> error::Result<()> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+init_zeroed!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char,);
+init_zeroed!(
+    AtomicBool,
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicUsize,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicIsize,
+);
+
+unsafe impl<T: ?Sized> InitInPlace for PhantomData<T> {
+    const ZEROED: bool = true;
+
+    unsafe fn init_in_place(_ptr: *mut Self) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl<T: InitInPlace, const N: usize> InitInPlace for [T; N] {
+    const ZEROED: bool = T::ZEROED;
+
+    unsafe fn init_in_place(ptr: *mut Self) -> error::Result<()> {
+        if !T::ZEROED {
+            for i in 0..N {
+                unsafe { T::init_in_place(ptr.cast::<T>().add(i))? };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that bytes written by another process hold a valid `Self`, since reading
+/// e.g. a `bool` that is neither 0 nor 1 is undefined behavior. The
+/// [`FromShm`](shm_derive::FromShm) derive implements it by checking an enum's tag and
+/// then every field of the object, and requires each field type to implement it.
+///
+/// # Safety
+///
+/// [`validate_in_place`](Self::validate_in_place) may only succeed if the bytes at
+/// `ptr` are a valid `Self`, and [`ALWAYS_VALID`](Self::ALWAYS_VALID) may only be
+/// true if every bit pattern is a valid `Self`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be checked when it is read from a segment",
+    label = "`{Self}` is not `ValidateInPlace`",
+    note = "derive `FromShm` for types nested in a `FromShm` type"
+)]
+pub unsafe trait ValidateInPlace {
+    /// Whether every bit pattern is a valid `Self`, in which case
+    /// [`validate_in_place`](Self::validate_in_place) always succeeds.
+    const ALWAYS_VALID: bool = false;
+
+    /// Checks the `Self` at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads of `size_of::<Self>()` bytes. It may be unaligned,
+    /// e.g. for a field of a packed struct.
+    unsafe fn validate_in_place(ptr: *const Self) -> error::Result<()>;
+}
+
+/// Implements [`ValidateInPlace`] for types that any bit pattern is valid for.
+macro_rules! always_valid {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            unsafe impl ValidateInPlace for $ty {
+                const ALWAYS_VALID: bool = true;
+
+                unsafe fn validate_in_place(_ptr: *const Self) -> error::Result<()> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+always_valid!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64,);
+always_valid!(
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicUsize,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicIsize,
+);
+
+unsafe impl ValidateInPlace for bool {
+    unsafe fn validate_in_place(ptr: *const Self) -> error::Result<()> {
+        match unsafe { ptr.cast::<u8>().read() } {
+            0 | 1 => Ok(()),
+            _ => Err(error::Error::new(error::ErrorKind::ValueError("bool"))),
+        }
+    }
+}
+
+unsafe impl ValidateInPlace for AtomicBool {
+    unsafe fn validate_in_place(ptr: *const Self) -> error::Result<()> {
+        unsafe { bool::validate_in_place(ptr.cast()) }
+    }
+}
+
+unsafe impl ValidateInPlace for char {
+    unsafe fn validate_in_place(ptr: *const Self) -> error::Result<()> {
+        match char::from_u32(unsafe { ptr.cast::<u32>().read_unaligned() }) {
+            Some(_) => Ok(()),
+            None => Err(error::Error::new(error::ErrorKind::ValueError("char"))),
+        }
+    }
+}
+
+unsafe impl<T: ?Sized> ValidateInPlace for PhantomData<T> {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl<T: ValidateInPlace, const N: usize> ValidateInPlace for [T; N] {
+    const ALWAYS_VALID: bool = T::ALWAYS_VALID;
+
+    unsafe fn validate_in_place(ptr: *const Self) -> error::Result<()> {
+        if !T::ALWAYS_VALID {
+            for i in 0..N {
+                unsafe { T::validate_in_place(ptr.cast::<T>().add(i))? };
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! NUMA placement of a segment's pages. See [`OpenOptions::numa`] and
+//! [`Shm::numa_nodes`].
+//!
+//! [`OpenOptions::numa`]: crate::OpenOptions::numa
+//! [`Shm::numa_nodes`]: crate::Shm::numa_nodes
+
+use std::ptr::{self, NonNull};
+
+use nix::errno::Errno;
+use nix::libc::{self, c_int, c_ulong, c_void};
+
+/// Moves pages that are already allocated so that they follow the new policy.
+const MPOL_MF_MOVE: c_int = 1 << 1;
+
+/// Where the kernel allocates a segment's pages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Allocate only on these nodes, failing if they are out of memory.
+    Bind(Vec<usize>),
+    /// Spread pages round-robin across these nodes.
+    Interleave(Vec<usize>),
+    /// Allocate on this node while it has free memory, and elsewhere otherwise.
+    Preferred(usize),
+}
+
+impl NumaPolicy {
+    fn mode(&self) -> c_int {
+        match self {
+            NumaPolicy::Bind(_) => libc::MPOL_BIND,
+            NumaPolicy::Interleave(_) => libc::MPOL_INTERLEAVE,
+            NumaPolicy::Preferred(_) => libc::MPOL_PREFERRED,
+        }
+    }
+
+    fn nodes(&self) -> &[usize] {
+        match self {
+            NumaPolicy::Bind(nodes) | NumaPolicy::Interleave(nodes) => nodes,
+            NumaPolicy::Preferred(node) => std::slice::from_ref(node),
+        }
+    }
+
+    fn mask(&self) -> Vec<c_ulong> {
+        let bits = c_ulong::BITS as usize;
+        let max = self.nodes().iter().max().map_or(0, |node| node + 1);
+        let mut mask = vec![0; max.div_ceil(bits).max(1)];
+        for node in self.nodes() {
+            mask[node / bits] |= 1 << (node % bits);
+        }
+        mask
+    }
+}
+
+/// Applies `policy` to the `len` bytes at `ptr` with `mbind`, migrating pages that
+/// are already allocated.
+pub(crate) fn bind(ptr: NonNull<c_void>, len: usize, policy: &NumaPolicy) -> nix::Result<()> {
+    let mask = policy.mask();
+    // The kernel ignores the last bit of the mask.
+    let maxnode = mask.len() * c_ulong::BITS as usize + 1;
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            ptr.as_ptr(),
+            len,
+            policy.mode(),
+            mask.as_ptr(),
+            maxnode,
+            MPOL_MF_MOVE,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+/// Returns the nodes that the allocated pages among the `len` bytes at `ptr` reside
+/// on, in ascending order.
+pub(crate) fn nodes(ptr: NonNull<c_void>, len: usize) -> nix::Result<Vec<usize>> {
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let pages: Vec<*mut c_void> = (0..len.div_ceil(page))
+        .map(|i| unsafe { ptr.as_ptr().byte_add(i * page) })
+        .collect();
+    let mut status: Vec<c_int> = vec![0; pages.len()];
+    // Without a list of target nodes, move_pages only reports where pages are.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_move_pages,
+            0,
+            pages.len(),
+            pages.as_ptr(),
+            ptr::null::<c_int>(),
+            status.as_mut_ptr(),
+            0,
+        )
+    };
+    Errno::result(res)?;
+    // Pages that were never touched report a negative errno instead of a node.
+    let mut nodes: Vec<usize> = status
+        .into_iter()
+        .filter_map(|node| usize::try_from(node).ok())
+        .collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+    Ok(nodes)
+}
@@ -0,0 +1,243 @@
+//! A bounded queue of `Copy` messages in a named segment, shared by one sending and
+//! one receiving process.
+//!
+//! Without an [`EventNotifier`], [`send`](MsgQueue::send) and
+//! [`recv`](MsgQueue::recv) spin until the queue has room or a message. Give each
+//! side a waker with [`set_waker`](MsgQueue::set_waker) and its peer's notifier with
+//! [`set_peer`](MsgQueue::set_peer) to block on the eventfd instead.
+
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix::errno::Errno;
+
+use crate::error::{Error, ErrorKind, Result};
+#[cfg(feature = "async")]
+use crate::event::AsyncEventNotifier;
+use crate::event::{EventFlag, EventNotifier};
+use crate::sync::Spinlock;
+use crate::{FromShm, Shm, ShmInit};
+
+pub struct MsgQueue<T: Copy> {
+    ring: Ring<T>,
+    waker: Option<EventNotifier>,
+}
+
+impl<T: Copy> MsgQueue<T> {
+    /// Creates a queue with room for `cap` messages. Fails with `EINVAL` if `cap` is
+    /// zero or `T` is zero-sized.
+    pub fn new(name: &str, cap: usize) -> Result<Self> {
+        if size_of::<T>() == 0 || cap == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut shm = Shm::new(name, Ring::<T>::size(cap))?;
+        let hdr = Header::shm_init_mut(&mut shm)?;
+        hdr.cap = cap;
+        Self::from_shm(shm)
+    }
+
+    /// Opens a queue created with [`new`](Self::new) by another process.
+    pub fn open(name: &str) -> Result<Self> {
+        let shm = Shm::options()
+            .mode(0o644)
+            .read(true)
+            .write(true)
+            .open(name)?;
+        Self::from_shm(shm)
+    }
+
+    fn from_shm(shm: Shm) -> Result<Self> {
+        let ring = Ring::new(shm)?;
+        Ok(MsgQueue { ring, waker: None })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.header().cap
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.header().len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Sets the notifier used to wake a peer waiting on this queue: the receiver
+    /// when sending, and the sender when receiving.
+    pub fn set_peer(&mut self, peer: EventNotifier) {
+        self.ring.peer = Some(peer);
+    }
+
+    /// Sets the notifier that [`send`](Self::send) and [`recv`](Self::recv) block on
+    /// while the queue is full or empty. The peer must notify it, i.e. hold a copy
+    /// sent with [`EventNotifier::send`] as its [`peer`](Self::set_peer).
+    pub fn set_waker(&mut self, waker: EventNotifier) {
+        self.waker = Some(waker);
+    }
+
+    pub fn try_send(&mut self, val: T) -> Result<()> {
+        self.ring.try_send(val)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T> {
+        self.ring.try_recv()
+    }
+
+    pub fn send(&mut self, val: T) -> Result<()> {
+        // try_send borrows the header mutably, so the flag in it is passed by pointer.
+        let flag: *const EventFlag = &self.ring.header().not_full;
+        let ready = || match self.ring.try_send(val) {
+            Err(e) if matches!(e.kind(), ErrorKind::QueueFull) => None,
+            r => Some(r),
+        };
+        match &self.waker {
+            Some(waker) => unsafe { waker.wait_until_raw(flag, ready)? },
+            None => spin(ready),
+        }
+    }
+
+    pub fn recv(&mut self) -> Result<T> {
+        // try_recv borrows the header mutably, so the flag in it is passed by pointer.
+        let flag: *const EventFlag = &self.ring.header().not_empty;
+        let ready = || match self.ring.try_recv() {
+            Err(e) if matches!(e.kind(), ErrorKind::QueueEmpty) => None,
+            r => Some(r),
+        };
+        match &self.waker {
+            Some(waker) => unsafe { waker.wait_until_raw(flag, ready)? },
+            None => spin(ready),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn send_async(&mut self, val: T, notifier: &AsyncEventNotifier) -> Result<()> {
+        let flag: *const EventFlag = &self.ring.header().not_full;
+        let ready = || match self.ring.try_send(val) {
+            Err(e) if matches!(e.kind(), ErrorKind::QueueFull) => None,
+            r => Some(r),
+        };
+        unsafe { notifier.wait_until_raw(flag, ready).await? }
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn recv_async(&mut self, notifier: &AsyncEventNotifier) -> Result<T> {
+        let flag: *const EventFlag = &self.ring.header().not_empty;
+        let ready = || match self.ring.try_recv() {
+            Err(e) if matches!(e.kind(), ErrorKind::QueueEmpty) => None,
+            r => Some(r),
+        };
+        unsafe { notifier.wait_until_raw(flag, ready).await? }
+    }
+}
+
+/// Calls `ready` until it returns a value.
+fn spin<T>(mut ready: impl FnMut() -> Option<T>) -> T {
+    loop {
+        if let Some(val) = ready() {
+            return val;
+        }
+        std::hint::spin_loop();
+    }
+}
+
+/// The segment and the messages in it, split from the waker so that a closure can
+/// borrow one while the other waits.
+struct Ring<T> {
+    shm: Shm,
+    data: NonNull<T>,
+    peer: Option<EventNotifier>,
+}
+
+impl<T: Copy> Ring<T> {
+    /// Bytes a queue of `cap` messages takes up, leaving room to align them.
+    fn size(cap: usize) -> usize {
+        size_of::<Header>() + align_of::<T>() - 1 + cap * size_of::<T>()
+    }
+
+    fn new(mut shm: Shm) -> Result<Self> {
+        let cap = Header::from_shm(&shm)?.cap;
+        if cap == 0 || shm.len() < Self::size(cap) {
+            return Err(Error::new(ErrorKind::SizeError(shm.len())));
+        }
+        let ptr = shm[size_of::<Header>()..].as_mut_ptr();
+        let data = NonNull::new(ptr.wrapping_add(ptr.align_offset(align_of::<T>())))
+            .unwrap()
+            .cast();
+        Ok(Ring {
+            shm,
+            data,
+            peer: None,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        // The header was checked when the queue was created or opened.
+        Header::from_shm(&self.shm).unwrap()
+    }
+
+    fn header_mut(&mut self) -> &mut Header {
+        Header::from_shm_mut(&mut self.shm).unwrap()
+    }
+
+    fn try_send(&mut self, val: T) -> Result<()> {
+        let data = self.data;
+        let hdr = self.header_mut();
+        // TODO: Use Rust's standard library mutex, if possible.
+        hdr.wr_lock.lock()?;
+        if hdr.len.load(Ordering::Acquire) == hdr.cap {
+            hdr.wr_lock.unlock()?;
+            return Err(Error::new(ErrorKind::QueueFull));
+        }
+        unsafe {
+            data.as_ptr().add(hdr.wrp).write(val);
+        }
+        hdr.wrp = (hdr.wrp + 1) % hdr.cap;
+        // The receiver only reads the slot once it sees the new length.
+        hdr.len.fetch_add(1, Ordering::Release);
+        hdr.wr_lock.unlock()?;
+        if let Some(peer) = &self.peer {
+            peer.notify(&self.header().not_empty)?;
+        }
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<T> {
+        let data = self.data;
+        let hdr = self.header_mut();
+        // TODO: Use Rust's standard library mutex, if possible.
+        hdr.rd_lock.lock()?;
+        if hdr.len.load(Ordering::Acquire) == 0 {
+            hdr.rd_lock.unlock()?;
+            return Err(Error::new(ErrorKind::QueueEmpty));
+        }
+        let val = unsafe { data.as_ptr().add(hdr.rdp).read() };
+        hdr.rdp = (hdr.rdp + 1) % hdr.cap;
+        // The sender only reuses the slot once it sees the new length.
+        hdr.len.fetch_sub(1, Ordering::Release);
+        hdr.rd_lock.unlock()?;
+        if let Some(peer) = &self.peer {
+            peer.notify(&self.header().not_full)?;
+        }
+        Ok(val)
+    }
+}
+
+/// The queue's state, shared by both sides. `len` is the only field both of them
+/// write; `rdp` and `wrp` each belong to one side and are guarded by its lock.
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+struct Header {
+    cap: usize,
+    len: AtomicUsize,
+    rdp: usize,
+    wrp: usize,
+    rd_lock: Spinlock,
+    wr_lock: Spinlock,
+    not_empty: EventFlag,
+    not_full: EventFlag,
+}
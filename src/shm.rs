@@ -1,23 +1,37 @@
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::num::NonZero;
-use std::ops::{Deref, DerefMut};
-use std::os::fd::{AsRawFd, OwnedFd};
-use std::path::PathBuf;
+use std::ops::{Deref, DerefMut, Range};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
 use std::ptr::NonNull;
 use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use nix::errno::Errno;
-use nix::sys::mman::shm_unlink;
-use nix::unistd::ftruncate;
 use nix::{
-    fcntl::OFlag,
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag, SealFlag},
     libc::c_void,
     libc::off_t,
-    sys::mman::{mmap, msync, munmap, shm_open, MapFlags, MsFlags, ProtFlags},
-    sys::stat::{fstat, Mode},
+    sys::mman::{
+        madvise, mlock, mmap_anonymous, msync, munmap, MapFlags, MmapAdvise, MsFlags, ProtFlags,
+    },
+    sys::stat::{fstat, Mode, SFlag},
 };
 
-use crate::{error::Result, ShmInit};
+use crate::backend::{Anonymous, Backend, Fd, File, HugePageSize, Memfd, PosixShm};
+use crate::error::{Error, ErrorKind, Result};
+use crate::fd::{recv_fds, send_fds};
+use crate::numa::{self, NumaPolicy};
+use crate::{FromShm, ShmInit};
+
+/// The environment variable [`Shm::inherit`] uses by default to tell a child which
+/// descriptor holds its segment.
+pub const INHERIT_VAR: &str = "SHMOO_FD";
 
 pub struct OpenOptions {
     mode: Mode,
@@ -25,22 +39,192 @@ pub struct OpenOptions {
     prot: ProtFlags,
     flgs: MapFlags,
     offset: off_t,
+    seals: SealFlag,
+    required_seals: SealFlag,
+    huge: Option<HugePageSize>,
+    thp: bool,
+    lock: bool,
+    numa: Option<NumaPolicy>,
 }
 
 impl OpenOptions {
+    /// Opens the named segment like [`open_backend`](Self::open_backend), keeping
+    /// the objects its creator constructed and leaving it in place on drop.
     pub fn open(self, name: &str) -> Result<Shm> {
-        let name = OpenOptions::prepend_slash(name);
-        let fd = shm_open(name.as_str(), self.oflg, self.mode)?;
-        let statbuf = fstat(fd.as_raw_fd())?;
-        let len = statbuf.st_size as usize;
-        Self::map_raw(fd, name, len, self.prot, self.flgs, self.offset)
+        self.open_backend(PosixShm::new(name))
     }
 
     pub fn map(self, name: &str, len: usize) -> Result<Shm> {
-        let name = Self::prepend_slash(name);
-        let fd = shm_open(name.as_str(), self.oflg, self.mode)?;
-        ftruncate(&fd, len as i64)?;
-        Self::map_raw(fd, name, len, self.prot, self.flgs, self.offset)
+        self.map_backend(PosixShm::new(name), len)
+    }
+
+    /// Maps a segment backed by `memfd_create` instead of a named POSIX segment. It
+    /// has no name to collide with and is freed once every descriptor and mapping of
+    /// it is closed. Share it with another process through its [`fd`](Shm::fd).
+    pub fn map_anonymous(self, len: usize) -> Result<Shm> {
+        let seals = self.seals;
+        if seals.contains(SealFlag::F_SEAL_WRITE) {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut shm = match self.huge {
+            Some(huge) => self
+                .map_backend(Memfd::new().huge_pages(huge), len)
+                .map_err(|err| refused("MAP_HUGETLB", err))?,
+            None => self.map_backend(Memfd::new(), len)?,
+        };
+        if !seals.is_empty() {
+            shm.seal(seals)?;
+        }
+        Ok(shm)
+    }
+
+    /// Maps a `MAP_SHARED | MAP_ANONYMOUS` segment. It has no descriptor, so it can
+    /// only be shared with children created by `fork`, which inherit the mapping.
+    pub fn map_shared_anonymous(self, len: usize) -> Result<Shm> {
+        let mut anonymous = Anonymous::new();
+        if let Some(huge) = self.huge {
+            anonymous = anonymous.huge_pages(huge);
+        }
+        self.map_backend(anonymous, len)
+    }
+
+    /// Maps the segment a parent passed to this process with
+    /// [`inherit_as`](Shm::inherit_as), validating it like [`map_fd`](Self::map_fd).
+    /// Fails with `EBADF` if `var` does not name an open regular file, which shared
+    /// memory segments are.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor named by `var` must have been inherited from the parent and
+    /// not be owned by anything else in this process, since the returned `Shm` takes
+    /// ownership of it. `var` is removed from the environment so that it is only
+    /// taken once, so no other thread may read or write the environment meanwhile.
+    pub unsafe fn map_inherited(self, var: &str) -> Result<Shm> {
+        let fd = std::env::var(var)
+            .ok()
+            .and_then(|fd| fd.parse::<RawFd>().ok())
+            .ok_or(Errno::EBADF)?;
+        // Make sure the descriptor is open and holds a segment before taking
+        // ownership of it.
+        let stat = fstat(fd)?;
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG {
+            return Err(Errno::EBADF.into());
+        }
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        std::env::remove_var(var);
+        self.map_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Maps a regular file at `path` instead of a POSIX segment, so its contents
+    /// outlive the process and survive a reboot. Like [`map`](Self::map), the file is
+    /// sized to hold `len` bytes and its header is reset; use
+    /// [`open_file`](Self::open_file) to map it again later. Changes reach the disk
+    /// after a [`flush`](Shm::flush) or whenever the kernel writes them back.
+    pub fn map_file<P: AsRef<Path>>(self, path: P, len: usize) -> Result<Shm> {
+        self.map_backend(File::new(path.as_ref()), len)
+    }
+
+    /// Maps a file previously created with [`map_file`](Self::map_file), keeping the
+    /// objects already constructed in it.
+    pub fn open_file<P: AsRef<Path>>(self, path: P) -> Result<Shm> {
+        self.open_backend(File::new(path.as_ref()))
+    }
+
+    /// Maps an existing segment from a descriptor, e.g. one received with
+    /// [`Shm::recv`]. Instead of being reinitialized, the segment's header is checked
+    /// against the size of the file, so the mapping must be readable.
+    pub fn map_fd(self, fd: OwnedFd) -> Result<Shm> {
+        self.open_backend(Fd::new(fd))
+    }
+
+    /// Maps the named segment [`private`](Self::private)ly, validating it like
+    /// [`open`](Self::open), so that this process's changes never disturb the
+    /// segment's writers.
+    pub fn open_private(self, name: &str) -> Result<Shm> {
+        let mut backend = PosixShm::new(name);
+        backend.open(self.oflg, self.mode)?;
+        let fd = backend.fd().ok_or(Errno::EBADF)?.try_clone_to_owned()?;
+        self.private(true).map_fd(fd)
+    }
+
+    /// Opens the named segment read-only. See [`open_backend_ref`](Self::open_backend_ref).
+    pub fn open_ref(self, name: &str) -> Result<ShmRef> {
+        self.open_backend_ref(PosixShm::new(name))
+    }
+
+    /// Opens an existing segment through `backend` like
+    /// [`open_backend`](Self::open_backend), but with a read-only descriptor and
+    /// mapping whatever the other options say. The segment is left in place when
+    /// the returned [`ShmRef`] is dropped.
+    pub fn open_backend_ref<B: Backend + 'static>(mut self, backend: B) -> Result<ShmRef> {
+        self.oflg = (self.oflg - OFlag::O_ACCMODE) | OFlag::O_RDONLY;
+        self.prot = ProtFlags::PROT_READ;
+        Ok(ShmRef(self.open_mapped(backend)?))
+    }
+
+    /// Receives a segment sent with [`Shm::send`] and maps it with [`map_fd`](Self::map_fd).
+    pub fn recv(self, stream: &UnixStream) -> Result<Shm> {
+        let (fd, len) = recv_segment(stream)?;
+        let shm = self.map_fd(fd)?;
+        if shm.len != len {
+            return Err(Error::new(ErrorKind::HeaderError));
+        }
+        Ok(shm)
+    }
+
+    /// Receives a segment sent with [`Shm::send`] and maps it read-only, e.g. one
+    /// that the sender sealed with `F_SEAL_WRITE`. See
+    /// [`open_backend_ref`](Self::open_backend_ref).
+    pub fn recv_ref(self, stream: &UnixStream) -> Result<ShmRef> {
+        let (fd, len) = recv_segment(stream)?;
+        let shm = self.open_backend_ref(Fd::new(fd))?;
+        if shm.0.len != len {
+            return Err(Error::new(ErrorKind::HeaderError));
+        }
+        Ok(shm)
+    }
+
+    /// Creates a segment of `len` bytes through `backend` and resets its header.
+    pub fn map_backend<B: Backend + 'static>(self, mut backend: B, len: usize) -> Result<Shm> {
+        self.check_writable()?;
+        backend.create(len + size_of::<Header>(), self.oflg, self.mode)?;
+        let mut shm = self.map_raw(Box::new(backend), len, true)?;
+        Header::init(&mut shm, len)?;
+        Ok(shm)
+    }
+
+    /// Opens an existing segment through `backend`. Instead of being reinitialized,
+    /// its header is checked against the size of the segment, so the mapping must be
+    /// readable. The segment belongs to whoever created it, so it is left in place
+    /// when the mapping is dropped or fails to open.
+    pub fn open_backend<B: Backend + 'static>(self, backend: B) -> Result<Shm> {
+        self.check_writable()?;
+        self.open_mapped(backend)
+    }
+
+    fn open_mapped<B: Backend + 'static>(self, mut backend: B) -> Result<Shm> {
+        backend.open(self.oflg, self.mode)?;
+        if !self.required_seals.is_empty() {
+            let fd = backend.fd().ok_or(Errno::EBADF)?;
+            let seals = SealFlag::from_bits_retain(fcntl(fd.as_raw_fd(), FcntlArg::F_GET_SEALS)?);
+            let missing = self.required_seals - seals;
+            if !missing.is_empty() {
+                return Err(Error::new(ErrorKind::SealError(missing)));
+            }
+        }
+        let len = backend
+            .size()?
+            .checked_sub(size_of::<Header>())
+            .ok_or(Error::new(ErrorKind::HeaderError))?;
+        let mut shm = self.map_raw(Box::new(backend), len, false)?;
+        Header::validate(&shm)?;
+        // Backends may round the segment up, e.g. to a whole huge page.
+        let len = Header::from_shm(&shm).len;
+        if len != shm.len {
+            shm.remap(len)?;
+        }
+        shm.gen = Header::from_shm(&shm).gen.load(Ordering::Acquire);
+        Ok(shm)
     }
 
     pub fn mode(mut self, mode: u32) -> Self {
@@ -75,6 +259,9 @@ impl OpenOptions {
         self
     }
 
+    /// Every [`Shm`] hands out mutable references to its segment, so mapping one
+    /// fails with `EACCES` unless this is set. Map a segment read-only as a
+    /// [`ShmRef`] instead.
     pub fn write(mut self, writable: bool) -> Self {
         if writable {
             self.prot |= ProtFlags::PROT_WRITE;
@@ -93,48 +280,142 @@ impl OpenOptions {
         self
     }
 
+    /// Maps the segment `MAP_PRIVATE` instead of `MAP_SHARED`, so that writes through
+    /// the mapping are copied on write and never reach the segment or its peers.
+    /// Pages that this process has not written yet still show the peers' writes.
+    pub fn private(mut self, private: bool) -> Self {
+        if private {
+            self.flgs = (self.flgs - MapFlags::MAP_SHARED) | MapFlags::MAP_PRIVATE;
+        } else {
+            self.flgs = (self.flgs - MapFlags::MAP_PRIVATE) | MapFlags::MAP_SHARED;
+        }
+        self
+    }
+
     /// Must be aligned to page boundary.
     pub fn offset(mut self, offset: off_t) -> Self {
         self.offset = offset;
         self
     }
 
-    fn map_raw(
-        fd: OwnedFd,
-        name: String,
-        len: usize,
-        prot: ProtFlags,
-        flgs: MapFlags,
-        offset: off_t,
-    ) -> Result<Shm> {
+    /// Seals to apply to an [`anonymous`](Self::map_anonymous) segment once its header
+    /// is initialized. See [`Shm::seal`]; like it, mapping fails with `EINVAL` if
+    /// they include `F_SEAL_WRITE`.
+    pub fn seal(mut self, seals: SealFlag) -> Self {
+        self.seals = seals;
+        self
+    }
+
+    /// Seals that a segment passed to [`map_fd`](Self::map_fd) must carry before it is
+    /// mapped, e.g. `F_SEAL_SHRINK` so that its owner cannot truncate it out from
+    /// under us and crash us with `SIGBUS`.
+    pub fn require_seals(mut self, seals: SealFlag) -> Self {
+        self.required_seals = seals;
+        self
+    }
+
+    /// Backs the segment with huge pages. Only [`anonymous`](Self::map_anonymous)
+    /// and [`shared anonymous`](Self::map_shared_anonymous) segments and hugetlbfs
+    /// files support this, and only while the kernel has free huge pages reserved
+    /// (see `/proc/sys/vm/nr_hugepages`). Otherwise mapping fails with
+    /// [`MapError`](ErrorKind::MapError).
+    pub fn huge_pages(mut self, size: HugePageSize) -> Self {
+        self.huge = Some(size);
+        self
+    }
+
+    /// Asks the kernel to back the mapping with transparent huge pages where it can,
+    /// with `madvise(MADV_HUGEPAGE)`. For POSIX and anonymous segments this only
+    /// takes effect if `/sys/kernel/mm/transparent_hugepage/shmem_enabled` allows it.
+    pub fn transparent_huge_pages(mut self, thp: bool) -> Self {
+        self.thp = thp;
+        self
+    }
+
+    /// Prefaults the whole mapping with `MAP_POPULATE`, so that first accesses do not
+    /// fault.
+    pub fn populate(mut self, populate: bool) -> Self {
+        if populate {
+            self.flgs |= MapFlags::MAP_POPULATE;
+        } else {
+            self.flgs &= !MapFlags::MAP_POPULATE;
+        }
+        self
+    }
+
+    /// Locks the mapping into RAM with `mlock` so that it is never paged out.
+    /// Unlike `MAP_LOCKED`, a failure, e.g. from exceeding `RLIMIT_MEMLOCK`, is
+    /// reported as a [`MapError`](ErrorKind::MapError).
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Maps with `MAP_NORESERVE`, so that no swap space is reserved for the mapping.
+    pub fn no_reserve(mut self, no_reserve: bool) -> Self {
+        if no_reserve {
+            self.flgs |= MapFlags::MAP_NORESERVE;
+        } else {
+            self.flgs &= !MapFlags::MAP_NORESERVE;
+        }
+        self
+    }
+
+    /// Places the segment's pages on NUMA nodes according to `policy`, with `mbind`.
+    /// The policy belongs to the segment, so it also applies to pages that peers
+    /// touch first, and pages that are already allocated are migrated.
+    pub fn numa(mut self, policy: NumaPolicy) -> Self {
+        self.numa = Some(policy);
+        self
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if !self.prot.contains(ProtFlags::PROT_WRITE) {
+            return Err(Errno::EACCES.into());
+        }
+        Ok(())
+    }
+
+    /// Maps the segment `backend` holds. `unlink` is whether this process owns the
+    /// segment, and so removes it when the mapping is dropped.
+    fn map_raw(&self, mut backend: Box<dyn Backend>, len: usize, unlink: bool) -> Result<Shm> {
+        let mut flgs = self.flgs;
+        if let Some(huge) = self.huge {
+            flgs |= huge.map_flags();
+        }
         // Since we embed a header, the length will never be zero.
-        let actual_len = len + size_of::<Header>();
-        let ptr = unsafe {
-            mmap(
-                None,
-                NonZero::new(actual_len).unwrap(),
-                prot,
-                flgs,
-                &fd,
-                offset,
-            )?
+        let ptr = backend.map(len + size_of::<Header>(), self.prot, flgs, self.offset);
+        let ptr = match ptr {
+            Ok(ptr) => ptr,
+            Err(err) => {
+                // Remove a segment we created like dropping the Shm would, so that it
+                // does not outlive a failed mapping. Opened segments are left alone.
+                if unlink {
+                    let _ = backend.unlink();
+                }
+                return Err(match self.huge {
+                    Some(_) => refused("MAP_HUGETLB", err),
+                    None => err,
+                });
+            }
         };
-        let mut shm = Shm {
+        let shm = Shm {
+            backend,
             ptr,
             len,
-            name: name.into(),
+            cursor: size_of::<Header>(),
+            gen: 0,
+            prot: self.prot,
+            flgs,
+            offset: self.offset,
+            thp: self.thp,
+            lock: self.lock,
+            numa: self.numa.clone(),
+            unlink,
         };
-        Header::init(&mut shm, len)?;
+        shm.advise()?;
         Ok(shm)
     }
-
-    fn prepend_slash(name: &str) -> String {
-        if name.chars().nth(0) != Some('/') {
-            String::from("/") + name
-        } else {
-            String::from(name)
-        }
-    }
 }
 
 impl Default for OpenOptions {
@@ -145,14 +426,33 @@ impl Default for OpenOptions {
             prot: ProtFlags::PROT_NONE,
             flgs: MapFlags::MAP_SHARED,
             offset: 0,
+            seals: SealFlag::empty(),
+            required_seals: SealFlag::empty(),
+            huge: None,
+            thp: false,
+            lock: false,
+            numa: None,
         }
     }
 }
 
 pub struct Shm {
-    name: PathBuf,
+    backend: Box<dyn Backend>,
     ptr: NonNull<c_void>,
     len: usize,
+    /// Offset of the next object this process constructs or reads. Attaching starts
+    /// at the first object rather than at the segment's shared allocation offset.
+    cursor: usize,
+    /// The header's generation when this process last mapped the segment.
+    gen: u64,
+    prot: ProtFlags,
+    flgs: MapFlags,
+    offset: off_t,
+    thp: bool,
+    lock: bool,
+    numa: Option<NumaPolicy>,
+    /// Whether dropping the mapping unlinks the segment.
+    unlink: bool,
 }
 
 impl Shm {
@@ -169,48 +469,548 @@ impl Shm {
         Shm::options().read(true).write(true).open(name)
     }
 
+    /// Opens a copy-on-write view of the named segment. See
+    /// [`OpenOptions::open_private`].
+    pub fn open_private(name: &str) -> Result<Self> {
+        Shm::options().read(true).write(true).open_private(name)
+    }
+
+    /// Opens the named segment read-only. See [`ShmRef`].
+    pub fn open_ref(name: &str) -> Result<ShmRef> {
+        Shm::options().open_ref(name)
+    }
+
+    pub fn anonymous(size: usize) -> Result<Self> {
+        Shm::options().read(true).write(true).map_anonymous(size)
+    }
+
+    pub fn shared_anonymous(size: usize) -> Result<Self> {
+        Shm::options()
+            .read(true)
+            .write(true)
+            .map_shared_anonymous(size)
+    }
+
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        Shm::options().read(true).write(true).map_fd(fd)
+    }
+
+    /// Maps the segment a parent passed to this process with [`inherit`](Shm::inherit).
+    ///
+    /// # Safety
+    ///
+    /// See [`OpenOptions::map_inherited`].
+    pub unsafe fn from_inherited() -> Result<Self> {
+        unsafe {
+            Shm::options()
+                .read(true)
+                .write(true)
+                .map_inherited(INHERIT_VAR)
+        }
+    }
+
+    /// Lets the child spawned by `cmd` inherit the segment. A duplicate of its
+    /// descriptor is held by `cmd`, left open across `exec` in the child only, and its
+    /// number is passed in the [`INHERIT_VAR`] environment variable for
+    /// [`from_inherited`](Shm::from_inherited).
+    pub fn inherit(&self, cmd: &mut Command) -> Result<()> {
+        self.inherit_as(cmd, INHERIT_VAR)
+    }
+
+    /// Like [`inherit`](Shm::inherit), but passes the descriptor in `var` so a child
+    /// can inherit several segments.
+    pub fn inherit_as(&self, cmd: &mut Command, var: &str) -> Result<()> {
+        // The closure owns the duplicate, so its number stays valid for as long as
+        // `cmd` can spawn, even after this `Shm` is dropped.
+        let fd = self.fd().ok_or(Errno::EBADF)?.try_clone_to_owned()?;
+        cmd.env(var, fd.as_raw_fd().to_string());
+        // Runs in the forked child, so the parent's duplicate stays close-on-exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty()))?;
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// The descriptor backing the segment, or `None` for a
+    /// [`shared_anonymous`](Shm::shared_anonymous) or [`SysV`](crate::backend::SysV)
+    /// segment.
+    pub fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.backend.fd()
+    }
+
+    /// A cursor over the bytes this `Shm` derefs to, starting at position 0. A `Shm`
+    /// has no stream position of its own, so stream-based readers and writers go
+    /// through a cursor.
+    pub fn cursor(&mut self) -> ShmCursor<'_> {
+        ShmCursor {
+            buf: &mut self[..],
+            pos: 0,
+        }
+    }
+
+    /// Maps the segment again as a cheap copy-on-write view that this process can
+    /// modify without disturbing its writers. Only the pages written through the
+    /// view are copied; the others keep showing the writers' changes, so a consistent
+    /// view requires the writers to be quiescent until it has been written to.
+    /// The view cannot be resized or sealed.
+    pub fn snapshot(&self) -> Result<Shm> {
+        let fd = self.fd().ok_or(Errno::EBADF)?.try_clone_to_owned()?;
+        Shm::options()
+            .read(true)
+            .write(true)
+            .offset(self.offset)
+            .private(true)
+            .map_fd(fd)
+    }
+
+    /// Fails with `EPERM` for a [`private`](OpenOptions::private) mapping, whose
+    /// changes to the segment itself would reach the live writers.
+    fn check_shared(&self) -> Result<()> {
+        if self.flgs.contains(MapFlags::MAP_PRIVATE) {
+            return Err(Errno::EPERM.into());
+        }
+        Ok(())
+    }
+
+    /// Bytes between this process's cursor and the end of the segment.
+    fn remaining(&self) -> usize {
+        self.len + size_of::<Header>() - self.cursor
+    }
+
+    fn raw_fd(&self) -> Result<RawFd> {
+        Ok(self.fd().ok_or(Errno::EBADF)?.as_raw_fd())
+    }
+
+    /// Sends a duplicate of the segment's descriptor and its length to the peer of
+    /// `stream`. Only processes that can connect to the socket see the segment, so
+    /// the sender should authenticate its peer (e.g. with `SO_PEERCRED`) first.
+    pub fn send(&self, stream: &UnixStream) -> Result<()> {
+        let hdr = Header::from_shm(self);
+        let fd = self.fd().ok_or(Errno::EBADF)?;
+        send_fds(stream, &[fd], &(hdr.len as u64).to_ne_bytes())
+    }
+
+    /// Receives a segment sent with [`send`](Shm::send) and maps it read-write.
+    pub fn recv(stream: &UnixStream) -> Result<Self> {
+        Shm::options().read(true).write(true).recv(stream)
+    }
+
+    /// Constructs a `T` at the cursor, padded to `T`'s alignment, and moves the
+    /// cursor past it, so that objects of any alignment can be constructed one after
+    /// another.
     pub fn construct<T: ShmInit>(&mut self) -> Result<&T> {
-        let hdr_bytes =
-            unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, size_of::<Header>()) };
-        let hdr = Header::from_bytes_mut(hdr_bytes);
-        let result = T::shm_init_mut(self)?;
-        hdr.nxt += size_of::<T>();
-        Ok(result)
+        self.construct_mut::<T>().map(|obj| &*obj)
     }
 
     pub fn construct_mut<T: ShmInit>(&mut self) -> Result<&mut T> {
-        let hdr_bytes =
-            unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, size_of::<Header>()) };
-        let hdr = Header::from_bytes_mut(hdr_bytes);
-        let result = T::shm_init_mut(self)?;
-        hdr.nxt += size_of::<T>();
-        Ok(result)
+        let obj: *mut T = T::shm_init_mut(self)?;
+        // The object may have been placed past the cursor to align it.
+        self.cursor = self.offset_of(obj.cast()) + T::CAPACITY;
+        Header::from_shm_mut(self).nxt = self.cursor;
+        Ok(unsafe { &mut *obj })
+    }
+
+    /// Constructs a `T` for which there is no [`ShmInit`] implementation by
+    /// zero-filling its memory and passing it to `init`, so that even a `T` too large
+    /// for the stack is built in place.
+    ///
+    /// # Safety
+    ///
+    /// `init` must leave a valid `T` behind.
+    pub unsafe fn construct_with<T, F>(&mut self, init: F) -> Result<&mut T>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> Result<()>,
+    {
+        let ptr = self.place::<MaybeUninit<T>>(size_of::<T>())?.as_ptr();
+        let obj = unsafe {
+            ptr.write_bytes(0, 1);
+            init(&mut *ptr)?;
+            (*ptr).assume_init_mut() as *mut T
+        };
+        self.cursor = self.offset_of(obj.cast()) + size_of::<T>();
+        Header::from_shm_mut(self).nxt = self.cursor;
+        Ok(unsafe { &mut *obj })
+    }
+
+    /// Returns where the next `T` goes: the first offset from the cursor that is
+    /// aligned for `T`, which must have room for `size` bytes. Fails with
+    /// `SizeError` if `size` bytes do not fit even without padding, and with
+    /// `AlignmentError` if they only fit unaligned.
+    pub fn place<T>(&self, size: usize) -> Result<NonNull<T>> {
+        if self.len() < size {
+            return Err(Error::new(ErrorKind::SizeError(self.len())));
+        }
+        let ptr = unsafe { self.ptr.as_ptr().byte_add(self.cursor) };
+        let padding = ptr.align_offset(align_of::<T>());
+        if padding > self.len() - size {
+            return Err(Error::new(ErrorKind::AlignmentError(align_of::<T>())));
+        }
+        Ok(unsafe { NonNull::new_unchecked(ptr.byte_add(padding).cast()) })
+    }
+
+    /// Constructs a `T` like [`construct_mut`](Shm::construct_mut), but returns a
+    /// handle to it that stays valid when the segment is resized.
+    pub fn construct_handle<T: ShmInit>(&mut self) -> Result<ShmHandle<T>> {
+        let obj: *const T = self.construct_mut::<T>()?;
+        Ok(ShmHandle::new(self.offset_of(obj.cast())))
+    }
+
+    /// Returns a handle to the `T` at this process's cursor, checking it with
+    /// [`FromShm`] first.
+    pub fn handle<T: FromShm>(&self) -> Result<ShmHandle<T>> {
+        let obj: *const T = T::from_shm(self)?;
+        Ok(ShmHandle::new(self.offset_of(obj.cast())))
+    }
+
+    /// The offset of `ptr`, which points into the mapping, from its start.
+    fn offset_of(&self, ptr: *const u8) -> usize {
+        ptr as usize - self.ptr.as_ptr() as usize
+    }
+
+    /// Resolves `handle`, first remapping the segment if a peer resized it, so that
+    /// the object cannot lie in pages a peer truncated away.
+    pub fn get<T>(&mut self, handle: ShmHandle<T>) -> Result<&T> {
+        self.refresh()?;
+        let ptr = self.resolve(handle)?;
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Like [`get`](Shm::get), but returns a mutable reference.
+    pub fn get_mut<T>(&mut self, handle: ShmHandle<T>) -> Result<&mut T> {
+        self.refresh()?;
+        let ptr = self.resolve(handle)?;
+        Ok(unsafe { &mut *ptr })
+    }
+
+    /// Grows or shrinks the segment to `len` bytes and bumps its generation, so that
+    /// peers remap it on their next [`refresh`](Shm::refresh). Objects that have
+    /// already been constructed cannot be truncated away. Peers that touch memory
+    /// past the new end before refreshing after a shrink are killed with `SIGBUS`.
+    pub fn resize(&mut self, len: usize) -> Result<()> {
+        self.check_shared()?;
+        let nxt = Header::from_shm(self).nxt;
+        if len + size_of::<Header>() < nxt {
+            return Err(Error::new(ErrorKind::SizeError(nxt - size_of::<Header>())));
+        }
+        self.backend.truncate(len + size_of::<Header>())?;
+        self.remap(len)?;
+        let hdr = Header::from_shm_mut(self);
+        hdr.len = len;
+        self.gen = hdr.gen.fetch_add(1, Ordering::AcqRel) + 1;
+        Ok(())
+    }
+
+    /// Remaps the segment if a peer [`resize`](Shm::resize)d it since this process
+    /// last mapped it. Returns whether it was remapped.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let gen = Header::from_shm(self).gen.load(Ordering::Acquire);
+        if gen == self.gen {
+            return Ok(false);
+        }
+        let len = Header::from_shm(self).len;
+        if self.backend.size()? < len + size_of::<Header>() {
+            return Err(Error::new(ErrorKind::HeaderError));
+        }
+        self.remap(len)?;
+        self.gen = gen;
+        Ok(true)
+    }
+
+    fn remap(&mut self, len: usize) -> Result<()> {
+        let old_len = self.len + size_of::<Header>();
+        let new_len = len + size_of::<Header>();
+        self.ptr = self.backend.remap(self.ptr, old_len, new_len)?;
+        self.len = len;
+        self.cursor = self.cursor.min(new_len);
+        self.advise()
+    }
+
+    fn resolve<T>(&self, handle: ShmHandle<T>) -> Result<*mut T> {
+        if handle.offset + size_of::<T>() > self.len + size_of::<Header>() {
+            return Err(Error::new(ErrorKind::SizeError(self.len)));
+        }
+        Ok(unsafe { self.ptr.as_ptr().byte_add(handle.offset) as *mut T })
     }
 
     pub fn options() -> OpenOptions {
         OpenOptions::default()
     }
+
+    /// The NUMA nodes that the segment's pages currently reside on. Pages that no
+    /// process has touched yet are not allocated on any node.
+    pub fn numa_nodes(&self) -> Result<Vec<usize>> {
+        Ok(numa::nodes(self.ptr, self.len + size_of::<Header>())?)
+    }
+
+    /// Applies the options that act on a mapping once it exists.
+    fn advise(&self) -> Result<()> {
+        let len = self.len + size_of::<Header>();
+        if self.thp {
+            unsafe { madvise(self.ptr, len, MmapAdvise::MADV_HUGEPAGE) }
+                .map_err(|errno| Error::new(ErrorKind::MapError("MADV_HUGEPAGE", errno)))?;
+        }
+        if self.lock {
+            unsafe { mlock(self.ptr, len) }
+                .map_err(|errno| Error::new(ErrorKind::MapError("mlock", errno)))?;
+        }
+        if let Some(policy) = &self.numa {
+            numa::bind(self.ptr, len, policy)
+                .map_err(|errno| Error::new(ErrorKind::MapError("mbind", errno)))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the whole mapping, header included, back to its file and waits for the
+    /// write to complete.
+    pub fn flush(&self) -> Result<()> {
+        self.msync(0, self.len + size_of::<Header>(), MsFlags::MS_SYNC)
+    }
+
+    /// Like [`flush`](Shm::flush), but only for `len` bytes starting `offset` bytes
+    /// into the segment's data, which is rounded out to whole pages.
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        if offset + len > self.len {
+            return Err(Error::new(ErrorKind::SizeError(self.len)));
+        }
+        self.msync(offset + size_of::<Header>(), len, MsFlags::MS_SYNC)
+    }
+
+    /// Schedules the whole mapping to be written back without waiting for it.
+    pub fn flush_async(&self) -> Result<()> {
+        self.msync(0, self.len + size_of::<Header>(), MsFlags::MS_ASYNC)
+    }
+
+    fn msync(&self, offset: usize, len: usize, flags: MsFlags) -> Result<()> {
+        // msync requires a page aligned address.
+        let page = unsafe { nix::libc::sysconf(nix::libc::_SC_PAGESIZE) } as usize;
+        let start = offset - offset % page;
+        unsafe {
+            let ptr = self.ptr.byte_add(start);
+            msync(ptr, len + offset - start, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Adds `seals` to an [`anonymous`](Shm::anonymous) segment. A `Shm` stays
+    /// writable, so this fails with `EINVAL` for `F_SEAL_WRITE`; use
+    /// [`seal_write`](Shm::seal_write) instead, or `F_SEAL_FUTURE_WRITE` to keep
+    /// writing through this mapping while denying new writable ones.
+    pub fn seal(&mut self, seals: SealFlag) -> Result<()> {
+        self.check_shared()?;
+        if seals.contains(SealFlag::F_SEAL_WRITE) {
+            return Err(Errno::EINVAL.into());
+        }
+        fcntl(self.raw_fd()?, FcntlArg::F_ADD_SEALS(seals))?;
+        Ok(())
+    }
+
+    /// Adds `F_SEAL_WRITE` and `seals` to an [`anonymous`](Shm::anonymous) segment
+    /// and maps it again read-only.
+    ///
+    /// The kernel refuses `F_SEAL_WRITE` while the segment has any writable shared
+    /// mapping, so this mapping is torn down first, and sealing fails with `EBUSY` if
+    /// a peer still maps it writable. The segment is unmapped on failure, like when
+    /// the `Shm` is dropped.
+    pub fn seal_write(mut self, seals: SealFlag) -> Result<ShmRef> {
+        self.check_shared()?;
+        let fd = self.raw_fd()?;
+        let actual_len = self.len + size_of::<Header>();
+        let reserved = actual_len.next_multiple_of(self.backend.page_size()?);
+        let reserved = NonZero::new(reserved).ok_or(Errno::EINVAL)?;
+        // Replace the writable mapping with an inaccessible reservation rather than
+        // unmapping it, so that dropping self on failure never unmaps memory that
+        // someone else has mapped at the same address since.
+        unsafe {
+            mmap_anonymous(
+                Some(self.ptr.addr()),
+                reserved,
+                ProtFlags::PROT_NONE,
+                MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED,
+            )?
+        };
+        fcntl(fd, FcntlArg::F_ADD_SEALS(seals | SealFlag::F_SEAL_WRITE))?;
+        self.prot &= !ProtFlags::PROT_WRITE;
+        let ptr = self
+            .backend
+            .map(actual_len, self.prot, self.flgs, self.offset)?;
+        unsafe { munmap(self.ptr, reserved.get())? };
+        self.ptr = ptr;
+        self.advise()?;
+        Ok(ShmRef(self))
+    }
+
+    pub fn seals(&self) -> Result<SealFlag> {
+        let seals = fcntl(self.raw_fd()?, FcntlArg::F_GET_SEALS)?;
+        Ok(SealFlag::from_bits_retain(seals))
+    }
 }
 
-impl Read for Shm {
+/// A read-only mapping of a segment, from [`Shm::open_ref`],
+/// [`OpenOptions::open_backend_ref`] or [`OpenOptions::recv_ref`]. It derefs to the segment's bytes and only
+/// reads objects that are `Copy`. That rules out interior mutability, like atomics
+/// and locks, which write to the segment even through a shared reference and would
+/// fault on the read-only mapping.
+pub struct ShmRef(Shm);
+
+impl ShmRef {
+    /// Checks the `T` at the start of the segment's objects with [`FromShm`].
+    pub fn read<T: FromShm + Copy>(&self) -> Result<&T> {
+        T::from_shm(&self.0)
+    }
+
+    /// Resolves `handle` like [`Shm::get`].
+    pub fn get<T: Copy>(&mut self, handle: ShmHandle<T>) -> Result<&T> {
+        self.0.get(handle)
+    }
+
+    /// Remaps the segment if a peer resized it. See [`Shm::refresh`].
+    pub fn refresh(&mut self) -> Result<bool> {
+        self.0.refresh()
+    }
+
+    /// The read-only descriptor backing the segment, if it has one.
+    pub fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.0.fd()
+    }
+
+    pub fn seals(&self) -> Result<SealFlag> {
+        self.0.seals()
+    }
+}
+
+impl Deref for ShmRef {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Refers to a `T` in a [`Shm`] by its offset rather than its address, so it cannot
+/// dangle when the segment is remapped by [`resize`](Shm::resize) or
+/// [`refresh`](Shm::refresh). Resolve it with [`get`](Shm::get) or
+/// [`get_mut`](Shm::get_mut); the returned reference borrows the `Shm`, which
+/// prevents remapping while it is alive.
+pub struct ShmHandle<T> {
+    offset: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ShmHandle<T> {
+    fn new(offset: usize) -> Self {
+        ShmHandle {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for ShmHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ShmHandle<T> {}
+
+/// A position in a [`Shm`]'s bytes, from [`Shm::cursor`], for serializers that read
+/// and write streams. Positions are relative to the start of the bytes the `Shm`
+/// derefs to, and reads and writes stop at the end of the segment.
+pub struct ShmCursor<'a> {
+    buf: &'a mut [u8],
+    pos: u64,
+}
+
+impl ShmCursor<'_> {
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Reads exactly `buf.len()` bytes starting at `offset`, without moving the
+    /// cursor. Fails with `UnexpectedEof` if they extend past the end.
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let range = self.range(offset, buf.len(), io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(&self.buf[range]);
+        Ok(())
+    }
+
+    /// Writes all of `buf` starting at `offset`, without moving the cursor. Fails
+    /// with `WriteZero` if it does not fit.
+    pub fn write_all_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let range = self.range(offset, buf.len(), io::ErrorKind::WriteZero)?;
+        self.buf[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn range(&self, offset: u64, len: usize, kind: io::ErrorKind) -> io::Result<Range<usize>> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|start| Some(start..start.checked_add(len)?))
+            .filter(|range| range.end <= self.buf.len())
+            .ok_or(io::Error::from(kind))
+    }
+
+    /// The cursor's index into the bytes, clamped to the end.
+    fn start(&self) -> usize {
+        self.buf
+            .len()
+            .min(self.pos.try_into().unwrap_or(usize::MAX))
+    }
+}
+
+impl Read for ShmCursor<'_> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let n = std::cmp::min(self.len, buf.len());
-        buf.copy_from_slice(&self[..n]);
+        let n = Read::read(&mut self.fill_buf()?, buf)?;
+        self.consume(n);
         Ok(n)
     }
 }
 
-impl Write for Shm {
+impl BufRead for ShmCursor<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let start = self.start();
+        Ok(&self.buf[start..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl Write for ShmCursor<'_> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let n = std::cmp::min(self.len, buf.len());
-        self[..n].copy_from_slice(&buf[..n]);
+        let start = self.start();
+        let n = Write::write(&mut &mut self.buf[start..], buf)?;
+        self.pos += n as u64;
         Ok(n)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        unsafe {
-            msync(self.ptr, self.len, MsFlags::MS_SYNC).map_err(|_| io::Error::last_os_error())
-        }
+        Ok(())
+    }
+}
+
+impl Seek for ShmCursor<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.buf.len() as u64, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        self.pos = base.checked_add_signed(offset).ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seek to a negative or overflowing position",
+        ))?;
+        Ok(self.pos)
     }
 }
 
@@ -218,9 +1018,8 @@ impl Deref for Shm {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
         unsafe {
-            let hdr = Header::from_shm(self);
-            let ptr = (self.ptr.as_ptr() as *const u8).add(hdr.nxt);
-            slice::from_raw_parts(ptr, self.len)
+            let ptr = (self.ptr.as_ptr() as *const u8).add(self.cursor);
+            slice::from_raw_parts(ptr, self.remaining())
         }
     }
 }
@@ -228,30 +1027,60 @@ impl Deref for Shm {
 impl DerefMut for Shm {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe {
-            let hdr = Header::from_shm(self);
-            let ptr = (self.ptr.as_ptr() as *mut u8).add(hdr.nxt);
-            slice::from_raw_parts_mut(ptr, self.len)
+            let ptr = (self.ptr.as_ptr() as *mut u8).add(self.cursor);
+            slice::from_raw_parts_mut(ptr, self.remaining())
         }
     }
 }
 
+/// Panics if the segment has no descriptor; use [`fd`](Shm::fd) where that can
+/// happen.
+impl AsFd for Shm {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd().expect("segment has no file descriptor")
+    }
+}
+
+impl AsRawFd for Shm {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
 impl Drop for Shm {
     fn drop(&mut self) {
-        unsafe {
-            munmap(self.ptr, self.len).unwrap();
-        }
-        // Ignore ENOENT in case another process already closed the file.
-        match shm_unlink(&self.name) {
-            Err(Errno::ENOENT) => (),
-            r => r.unwrap(),
+        // Errors cannot be reported from here, and panicking would abort an unwind.
+        let _ = self.backend.unmap(self.ptr, self.len + size_of::<Header>());
+        if self.unlink {
+            let _ = self.backend.unlink();
         }
     }
 }
 
+/// Receives a segment's descriptor and its length from [`Shm::send`].
+fn recv_segment(stream: &UnixStream) -> Result<(OwnedFd, usize)> {
+    let mut msg = [0; size_of::<u64>()];
+    let [fd] = recv_fds::<1>(stream, &mut msg)?;
+    Ok((fd, u64::from_ne_bytes(msg) as usize))
+}
+
+/// Reports an OS error caused by `option` as a [`MapError`](ErrorKind::MapError).
+fn refused(option: &'static str, err: Error) -> Error {
+    match err.kind() {
+        ErrorKind::IoError(io) => match io.raw_os_error() {
+            Some(errno) => Error::new(ErrorKind::MapError(option, Errno::from_raw(errno))),
+            None => err,
+        },
+        _ => err,
+    }
+}
+
 #[repr(C)]
 struct Header {
     len: usize,
     nxt: usize,
+    /// Bumped on every resize so that peers know to remap.
+    gen: AtomicU64,
 }
 
 impl Header {
@@ -259,6 +1088,18 @@ impl Header {
         let hdr = Header::from_shm_mut(shm);
         hdr.len = len;
         hdr.nxt = size_of::<Self>();
+        let gen = hdr.gen.load(Ordering::Acquire);
+        shm.gen = gen;
+        Ok(())
+    }
+
+    /// Checks that a header written by another process describes the mapping.
+    fn validate(shm: &Shm) -> Result<()> {
+        let hdr = Header::from_shm(shm);
+        let end = size_of::<Self>() + hdr.len;
+        if hdr.len > shm.len || hdr.nxt < size_of::<Self>() || hdr.nxt > end {
+            return Err(Error::new(ErrorKind::HeaderError));
+        }
         Ok(())
     }
 
@@ -276,8 +1117,4 @@ impl Header {
             &mut *(hdr_bytes.as_mut_ptr() as *mut Header)
         }
     }
-
-    fn from_bytes_mut(data: &mut [u8]) -> &mut Self {
-        unsafe { &mut *(data[..size_of::<Self>()].as_ptr() as *mut Header) }
-    }
 }
@@ -1,5 +1,5 @@
 use std::{
-    io::{Error, ErrorKind, Result},
+    io,
     mem::MaybeUninit,
     sync::{
         atomic::{AtomicU32, AtomicU8, Ordering},
@@ -7,24 +7,157 @@ use std::{
     },
 };
 
+use nix::errno::Errno;
 use nix::libc::{
-    pthread_cond_init, pthread_cond_signal, pthread_cond_t, pthread_cond_wait,
-    pthread_condattr_init, pthread_condattr_setpshared, pthread_condattr_t, pthread_mutex_init,
-    pthread_mutex_lock, pthread_mutex_t, pthread_mutex_unlock, pthread_mutexattr_init,
-    pthread_mutexattr_setpshared, pthread_mutexattr_t,
+    c_int, pthread_cond_init, pthread_cond_signal, pthread_cond_t, pthread_cond_wait,
+    pthread_condattr_init, pthread_condattr_setpshared, pthread_condattr_t,
+    pthread_mutex_consistent, pthread_mutex_init, pthread_mutex_lock, pthread_mutex_t,
+    pthread_mutex_unlock, pthread_mutexattr_init, pthread_mutexattr_setprotocol,
+    pthread_mutexattr_setpshared, pthread_mutexattr_setrobust, pthread_mutexattr_settype,
+    pthread_mutexattr_t, PTHREAD_MUTEX_ERRORCHECK, PTHREAD_MUTEX_NORMAL, PTHREAD_MUTEX_RECURSIVE,
+    PTHREAD_MUTEX_ROBUST, PTHREAD_MUTEX_STALLED, PTHREAD_PRIO_INHERIT, PTHREAD_PRIO_NONE,
+    PTHREAD_PRIO_PROTECT, PTHREAD_PROCESS_SHARED,
 };
 
-use crate::Shm;
+use crate::error::{Error, ErrorKind, Result};
+#[cfg(feature = "async")]
+use crate::event::{AsyncEventNotifier, EventFlag};
+use crate::{InitInPlace, Shm, ShmSafe, ValidateInPlace};
 
+// Not exposed by the libc crate.
+extern "C" {
+    fn pthread_mutexattr_setprioceiling(
+        attr: *mut pthread_mutexattr_t,
+        prioceiling: c_int,
+    ) -> c_int;
+}
+
+/// Calls a pthread function and returns early with a [`SyncError`](ErrorKind::SyncError)
+/// if it fails. Pthread functions return zero on success and an errno value on failure.
 macro_rules! check_err {
-    ($call:expr) => {
-        let err = $call;
-        if (err < 0) {
-            return Err(Error::from_raw_os_error(err));
+    ($func:ident($($arg:expr),* $(,)?)) => {
+        let err = $func($($arg),*);
+        if err != 0 {
+            return Err(Error::new(ErrorKind::SyncError(
+                stringify!($func),
+                Errno::from_raw(err),
+            )));
         }
     };
 }
 
+/// The type of a [`PosixMutex`], which determines how it behaves when relocked or
+/// unlocked by a thread that does not own it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MutexType {
+    /// No error checking; relocking deadlocks.
+    #[default]
+    Normal,
+    /// Relocking fails with `EDEADLK` and unlocking an unowned mutex fails with `EPERM`.
+    ErrorCheck,
+    /// The owner may lock the mutex multiple times and must unlock it as many times.
+    Recursive,
+}
+
+impl MutexType {
+    fn as_raw(self) -> c_int {
+        match self {
+            MutexType::Normal => PTHREAD_MUTEX_NORMAL,
+            MutexType::ErrorCheck => PTHREAD_MUTEX_ERRORCHECK,
+            MutexType::Recursive => PTHREAD_MUTEX_RECURSIVE,
+        }
+    }
+}
+
+/// The scheduling protocol of a [`PosixMutex`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MutexProtocol {
+    /// Locking the mutex does not affect the owner's priority.
+    #[default]
+    None,
+    /// The owner inherits the priority of the highest priority thread blocked on the
+    /// mutex, which avoids priority inversion.
+    Inherit,
+    /// The owner runs at the given priority ceiling while it holds the mutex.
+    Protect(c_int),
+}
+
+impl MutexProtocol {
+    fn as_raw(self) -> c_int {
+        match self {
+            MutexProtocol::None => PTHREAD_PRIO_NONE,
+            MutexProtocol::Inherit => PTHREAD_PRIO_INHERIT,
+            MutexProtocol::Protect(_) => PTHREAD_PRIO_PROTECT,
+        }
+    }
+}
+
+/// Builder for a process-shared [`PosixMutex`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MutexOptions {
+    kind: MutexType,
+    protocol: MutexProtocol,
+    robust: bool,
+}
+
+impl MutexOptions {
+    pub fn kind(mut self, kind: MutexType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: MutexProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// If the owner of a robust mutex dies while holding it, the next call to
+    /// [`lock`](PosixMutex::lock) acquires it but fails with `EOWNERDEAD`. The new
+    /// owner must repair the protected state and call
+    /// [`consistent`](PosixMutex::consistent) before unlocking.
+    pub fn robust(mut self, robust: bool) -> Self {
+        self.robust = robust;
+        self
+    }
+
+    pub fn build(self) -> Result<PosixMutex> {
+        let mut mtx = MaybeUninit::uninit();
+        unsafe {
+            self.build_in(mtx.as_mut_ptr())?;
+            Ok(mtx.assume_init())
+        }
+    }
+
+    /// Initializes a mutex at `ptr`, e.g. in a segment with
+    /// [`Shm::construct_with`](crate::Shm::construct_with). A pthread mutex may not
+    /// be moved once it is initialized, so one that is shared must be built in place.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes and aligned, and must not hold a mutex that is
+    /// in use.
+    pub unsafe fn build_in(self, ptr: *mut PosixMutex) -> Result<()> {
+        let robustness = if self.robust {
+            PTHREAD_MUTEX_ROBUST
+        } else {
+            PTHREAD_MUTEX_STALLED
+        };
+        unsafe {
+            let attr = &raw mut (*ptr).attr;
+            check_err!(pthread_mutexattr_init(attr));
+            check_err!(pthread_mutexattr_setpshared(attr, PTHREAD_PROCESS_SHARED));
+            check_err!(pthread_mutexattr_settype(attr, self.kind.as_raw()));
+            check_err!(pthread_mutexattr_setprotocol(attr, self.protocol.as_raw()));
+            if let MutexProtocol::Protect(ceiling) = self.protocol {
+                check_err!(pthread_mutexattr_setprioceiling(attr, ceiling));
+            }
+            check_err!(pthread_mutexattr_setrobust(attr, robustness));
+            check_err!(pthread_mutex_init(&raw mut (*ptr).mtx, attr));
+        }
+        Ok(())
+    }
+}
+
 #[repr(C)]
 pub struct PosixMutex {
     attr: pthread_mutexattr_t,
@@ -33,17 +166,11 @@ pub struct PosixMutex {
 
 impl PosixMutex {
     pub fn new() -> Result<Self> {
-        let mut attr = MaybeUninit::uninit();
-        let mut mtx = MaybeUninit::uninit();
-        unsafe {
-            check_err!(pthread_mutexattr_init(attr.as_mut_ptr()));
-            check_err!(pthread_mutexattr_setpshared(attr.as_mut_ptr(), 1));
-            check_err!(pthread_mutex_init(mtx.as_mut_ptr(), attr.as_mut_ptr()));
-            Ok(PosixMutex {
-                attr: attr.assume_init(),
-                mtx: mtx.assume_init(),
-            })
-        }
+        Self::options().build()
+    }
+
+    pub fn options() -> MutexOptions {
+        MutexOptions::default()
     }
 
     pub fn lock(&mut self) -> Result<()> {
@@ -59,6 +186,31 @@ impl PosixMutex {
         }
         Ok(())
     }
+
+    /// Marks a robust mutex whose previous owner died as consistent again.
+    pub fn consistent(&mut self) -> Result<()> {
+        unsafe {
+            check_err!(pthread_mutex_consistent(&raw mut self.mtx));
+        }
+        Ok(())
+    }
+}
+
+unsafe impl ShmSafe for PosixMutex {}
+
+unsafe impl InitInPlace for PosixMutex {
+    unsafe fn init_in_place(ptr: *mut Self) -> Result<()> {
+        unsafe { PosixMutex::options().build_in(ptr) }
+    }
+}
+
+// A pthread mutex is plain C data, so any bytes are a valid, if unusable, one.
+unsafe impl ValidateInPlace for PosixMutex {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[repr(C)]
@@ -69,16 +221,10 @@ pub struct PosixCondition {
 
 impl PosixCondition {
     pub fn new() -> Result<Self> {
-        let mut attr = MaybeUninit::uninit();
         let mut cond = MaybeUninit::uninit();
         unsafe {
-            check_err!(pthread_condattr_init(attr.as_mut_ptr()));
-            check_err!(pthread_condattr_setpshared(attr.as_mut_ptr(), 1));
-            check_err!(pthread_cond_init(cond.as_mut_ptr(), attr.as_mut_ptr()));
-            Ok(PosixCondition {
-                attr: attr.assume_init(),
-                cond: cond.assume_init(),
-            })
+            Self::init_in_place(cond.as_mut_ptr())?;
+            Ok(cond.assume_init())
         }
     }
 
@@ -97,6 +243,30 @@ impl PosixCondition {
     }
 }
 
+unsafe impl ShmSafe for PosixCondition {}
+
+unsafe impl InitInPlace for PosixCondition {
+    /// Initializes the condition variable directly at `ptr`, since it may not be
+    /// moved once it is initialized.
+    unsafe fn init_in_place(ptr: *mut Self) -> Result<()> {
+        unsafe {
+            let attr = &raw mut (*ptr).attr;
+            check_err!(pthread_condattr_init(attr));
+            check_err!(pthread_condattr_setpshared(attr, PTHREAD_PROCESS_SHARED));
+            check_err!(pthread_cond_init(&raw mut (*ptr).cond, attr));
+        }
+        Ok(())
+    }
+}
+
+unsafe impl ValidateInPlace for PosixCondition {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
+
 #[repr(transparent)]
 pub struct BinarySemaphore {
     inner: AtomicU8,
@@ -130,6 +300,27 @@ impl BinarySemaphore {
         }
         Ok(())
     }
+
+    /// Takes the semaphore if it has been posted, without waiting.
+    pub fn try_wait(&mut self) -> bool {
+        self.inner
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Waits for the semaphore without blocking the async runtime. The poster must
+    /// call [`EventNotifier::notify`](crate::event::EventNotifier::notify) with the
+    /// same `flag` after [`post`](Self::post) to wake the waiter.
+    #[cfg(feature = "async")]
+    pub async fn wait_async(
+        &mut self,
+        flag: &EventFlag,
+        notifier: &AsyncEventNotifier,
+    ) -> Result<()> {
+        notifier
+            .wait_until(flag, || self.try_wait().then_some(()))
+            .await
+    }
 }
 
 impl Default for BinarySemaphore {
@@ -138,6 +329,25 @@ impl Default for BinarySemaphore {
     }
 }
 
+unsafe impl ShmSafe for BinarySemaphore {}
+
+// All zero bytes is an unposted semaphore.
+unsafe impl InitInPlace for BinarySemaphore {
+    const ZEROED: bool = true;
+
+    unsafe fn init_in_place(_ptr: *mut Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl ValidateInPlace for BinarySemaphore {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
+
 static PID: LazyLock<u32> = LazyLock::new(std::process::id);
 
 #[repr(transparent)]
@@ -165,10 +375,11 @@ impl Spinlock {
             .compare_exchange(*PID, 0, Ordering::Release, Ordering::Relaxed)
         {
             Ok(_) => Ok(()),
-            Err(_) => Err(Error::new(
-                ErrorKind::InvalidInput,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
                 "process must own the Spinlock to unlock it",
-            )),
+            )
+            .into()),
         }
     }
 
@@ -196,3 +407,209 @@ impl Default for Spinlock {
         Self::new()
     }
 }
+
+unsafe impl ShmSafe for Spinlock {}
+
+// All zero bytes is the unlocked state.
+unsafe impl InitInPlace for Spinlock {
+    const ZEROED: bool = true;
+
+    unsafe fn init_in_place(_ptr: *mut Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl ValidateInPlace for Spinlock {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A fair spinlock that grants the lock to processes in the order they requested it.
+///
+/// Every waiter spins on the same counter, so prefer [`QueueLock`] when many processes
+/// contend for the lock at once.
+#[repr(C)]
+pub struct TicketLock {
+    next: AtomicU32,
+    serving: AtomicU32,
+}
+
+impl TicketLock {
+    pub fn new() -> Self {
+        TicketLock {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+        }
+    }
+
+    pub fn unlock(&mut self) -> Result<()> {
+        let serving = self.serving.load(Ordering::Relaxed);
+        if serving == self.next.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TicketLock must be locked to unlock it",
+            )
+            .into());
+        }
+        self.serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<()> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+impl Default for TicketLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl ShmSafe for TicketLock {}
+
+// All zero bytes is the unlocked state.
+unsafe impl InitInPlace for TicketLock {
+    const ZEROED: bool = true;
+
+    unsafe fn init_in_place(_ptr: *mut Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+unsafe impl ValidateInPlace for TicketLock {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A waiter's flag in a [`QueueLock`], on its own cache line so that waiters do not
+/// contend with each other while spinning.
+#[repr(C, align(64))]
+struct QueueSlot {
+    granted: AtomicU32,
+}
+
+impl QueueSlot {
+    fn new(granted: bool) -> Self {
+        QueueSlot {
+            granted: AtomicU32::new(granted as u32),
+        }
+    }
+}
+
+/// A fair array-based queue lock in which every waiter spins on its own slot.
+///
+/// Waiters are identified by slot indices rather than pointers, so the lock can live
+/// in shared memory. `N` must be a power of two and bounds the number of holders and
+/// waiters at once; [`lock`](Self::lock) fails with `WouldBlock` rather than make two
+/// waiters share a slot.
+#[repr(C)]
+pub struct QueueLock<const N: usize> {
+    tail: AtomicU32,
+    head: AtomicU32,
+    slots: [QueueSlot; N],
+}
+
+impl<const N: usize> QueueLock<N> {
+    pub fn new() -> Self {
+        const { assert!(N.is_power_of_two(), "QueueLock size must be a power of two") };
+        QueueLock {
+            tail: AtomicU32::new(0),
+            head: AtomicU32::new(0),
+            slots: std::array::from_fn(|i| QueueSlot::new(i == 0)),
+        }
+    }
+
+    pub fn unlock(&mut self) -> Result<()> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "QueueLock must be locked to unlock it",
+            )
+            .into());
+        }
+        let next = head.wrapping_add(1);
+        self.head.store(next, Ordering::Relaxed);
+        self.slot(next).granted.store(1, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<()> {
+        let ticket = loop {
+            // Read the head first so that it never runs ahead of the tail.
+            let head = self.head.load(Ordering::Acquire);
+            let ticket = self.tail.load(Ordering::Acquire);
+            if ticket.wrapping_sub(head) >= N as u32 {
+                // The lock may have been passed on while the tail was read.
+                if self.head.load(Ordering::Relaxed) != head {
+                    continue;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "QueueLock has as many waiters as slots",
+                )
+                .into());
+            }
+            if self
+                .tail
+                .compare_exchange_weak(
+                    ticket,
+                    ticket.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                break ticket;
+            }
+        };
+        let slot = self.slot(ticket);
+        while slot.granted.load(Ordering::Acquire) == 0 {
+            std::hint::spin_loop();
+        }
+        // Reset our slot for whoever wraps around to it next.
+        slot.granted.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn slot(&self, ticket: u32) -> &QueueSlot {
+        &self.slots[ticket as usize % N]
+    }
+}
+
+impl<const N: usize> Default for QueueLock<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> ShmSafe for QueueLock<N> {}
+
+unsafe impl<const N: usize> InitInPlace for QueueLock<N> {
+    unsafe fn init_in_place(ptr: *mut Self) -> Result<()> {
+        const { assert!(N.is_power_of_two(), "QueueLock size must be a power of two") };
+        // The first waiter to arrive is granted the lock.
+        unsafe { (*ptr).slots[0].granted.store(1, Ordering::Relaxed) };
+        Ok(())
+    }
+}
+
+unsafe impl<const N: usize> ValidateInPlace for QueueLock<N> {
+    const ALWAYS_VALID: bool = true;
+
+    unsafe fn validate_in_place(_ptr: *const Self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::ops::{Deref, DerefMut};
+use std::os::fd::OwnedFd;
+use std::ptr::NonNull;
+
+use crate::backend::PosixShm;
+use crate::error::Result;
+use crate::shm::{OpenOptions, Shm};
+use crate::{FromShm, ShmInit};
+
+/// A segment holding a single `T`, sized for it and constructed or checked when it
+/// is mapped, that derefs to the `T`.
+pub struct TypedShm<T> {
+    shm: Shm,
+    /// Points into `shm`, which is never remapped since it is not handed out mutably.
+    obj: NonNull<T>,
+}
+
+impl<T: ShmInit> TypedShm<T> {
+    /// Creates the named segment and constructs a `T` in it.
+    pub fn new(name: &str) -> Result<Self> {
+        Shm::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .exclusive(true)
+            .map_typed(name)
+    }
+
+    /// Creates an anonymous segment and constructs a `T` in it. Share it through
+    /// [`shm`](Self::shm) and map it in the peer with [`from_fd`](Self::from_fd).
+    pub fn anonymous() -> Result<Self> {
+        let shm = Shm::anonymous(capacity::<T>())?;
+        Self::construct(shm)
+    }
+
+    fn construct(mut shm: Shm) -> Result<Self> {
+        let obj = NonNull::from(shm.construct_mut::<T>()?);
+        Ok(TypedShm { shm, obj })
+    }
+}
+
+/// Room for a `T`, including padding to align it after the segment's header.
+fn capacity<T: ShmInit>() -> usize {
+    T::CAPACITY + align_of::<T>() - 1
+}
+
+impl<T: FromShm> TypedShm<T> {
+    /// Opens the named segment and checks the `T` in it with [`FromShm`].
+    pub fn open(name: &str) -> Result<Self> {
+        Shm::options().read(true).write(true).open_typed(name)
+    }
+
+    /// Maps a segment from a descriptor, e.g. one received with [`Shm::recv`], and
+    /// checks the `T` in it with [`FromShm`].
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        Self::check(Shm::from_fd(fd)?)
+    }
+
+    fn check(mut shm: Shm) -> Result<Self> {
+        let obj = NonNull::from(T::from_shm_mut(&mut shm)?);
+        Ok(TypedShm { shm, obj })
+    }
+}
+
+impl<T> TypedShm<T> {
+    /// The underlying segment, e.g. to [`send`](Shm::send) or
+    /// [`inherit`](Shm::inherit) it.
+    pub fn shm(&self) -> &Shm {
+        &self.shm
+    }
+}
+
+impl<T> Deref for TypedShm<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.obj.as_ref() }
+    }
+}
+
+impl<T> DerefMut for TypedShm<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.obj.as_mut() }
+    }
+}
+
+impl OpenOptions {
+    /// Creates the named segment with room for a `T` and constructs one in it.
+    pub fn map_typed<T: ShmInit>(self, name: &str) -> Result<TypedShm<T>> {
+        TypedShm::construct(self.map(name, capacity::<T>())?)
+    }
+
+    /// Opens the named segment without reinitializing it, like
+    /// [`open_backend`](Self::open_backend), and checks the `T` in it.
+    pub fn open_typed<T: FromShm>(self, name: &str) -> Result<TypedShm<T>> {
+        TypedShm::check(self.open_backend(PosixShm::new(name))?)
+    }
+}
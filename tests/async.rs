@@ -0,0 +1,77 @@
+#![cfg(feature = "async")]
+
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+
+use shmoo::event::{AsyncEventNotifier, EventFlag, EventNotifier};
+use shmoo::queue::MsgQueue;
+use shmoo::sync::BinarySemaphore;
+
+struct Shared {
+    sem: BinarySemaphore,
+    flag: EventFlag,
+}
+
+struct SharedPtr(*mut Shared);
+
+unsafe impl Send for SharedPtr {}
+
+#[tokio::test]
+async fn semaphore_wait_async() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let waker = EventNotifier::new().unwrap();
+    waker.send(&a).unwrap();
+    let notifier = EventNotifier::recv(&b).unwrap();
+    let waker = AsyncEventNotifier::new(waker).unwrap();
+
+    let mut shared = Box::new(Shared {
+        sem: BinarySemaphore::new(),
+        flag: EventFlag::new(),
+    });
+    let ptr = SharedPtr(&raw mut *shared);
+
+    let poster = thread::spawn(move || {
+        let ptr = ptr;
+        thread::sleep(Duration::from_millis(50));
+        let shared = unsafe { &mut *ptr.0 };
+        shared.sem.post().unwrap();
+        notifier.notify(&shared.flag).unwrap()
+    });
+
+    let Shared { sem, flag } = &mut *shared;
+    sem.wait_async(flag, &waker).await.unwrap();
+    assert!(poster.join().unwrap());
+}
+
+#[tokio::test]
+async fn queue_round_trip_async() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let waker = EventNotifier::new().unwrap();
+    waker.send(&a).unwrap();
+    let notifier = EventNotifier::recv(&b).unwrap();
+    let waker = AsyncEventNotifier::new(waker).unwrap();
+
+    let name = format!("/shmoo_async_queue_{}", std::process::id());
+    let mut queue = MsgQueue::<u64>::new(&name, 1).unwrap();
+    let peer = thread::spawn(move || {
+        let mut peer = MsgQueue::<u64>::open(&name).unwrap();
+        peer.set_peer(notifier);
+        for i in 0..3 {
+            thread::sleep(Duration::from_millis(10));
+            peer.send(i).unwrap();
+        }
+        for i in 0..3 {
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(peer.recv().unwrap(), 10 + i);
+        }
+    });
+
+    for i in 0..3 {
+        assert_eq!(queue.recv_async(&waker).await.unwrap(), i);
+    }
+    for i in 0..3 {
+        queue.send_async(10 + i, &waker).await.unwrap();
+    }
+    peer.join().unwrap();
+}
@@ -0,0 +1,9 @@
+#[test]
+fn derive() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/generics.rs");
+    t.compile_fail("tests/ui/unbounded_param.rs");
+    t.compile_fail("tests/ui/pointer_fields.rs");
+    t.compile_fail("tests/ui/shm_ref_atomic.rs");
+    t.compile_fail("tests/ui/packed_fields.rs");
+}
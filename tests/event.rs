@@ -0,0 +1,42 @@
+use std::os::unix::net::UnixStream;
+
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+use shmoo::event::{EventFlag, EventNotifier};
+
+fn readable(epoll: &Epoll) -> usize {
+    let mut events = [EpollEvent::empty()];
+    epoll.wait(&mut events, EpollTimeout::ZERO).unwrap()
+}
+
+#[test]
+fn notify_armed_consumer() {
+    let (producer, consumer) = UnixStream::pair().unwrap();
+    let waker = EventNotifier::new().unwrap();
+    waker.send(&consumer).unwrap();
+    let notifier = EventNotifier::recv(&producer).unwrap();
+
+    let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC).unwrap();
+    epoll
+        .add(&waker, EpollEvent::new(EpollFlags::EPOLLIN, 0))
+        .unwrap();
+
+    let flag = EventFlag::new();
+    assert!(!notifier.notify(&flag).unwrap());
+    assert_eq!(readable(&epoll), 0);
+
+    flag.arm();
+    assert!(notifier.notify(&flag).unwrap());
+    assert!(!flag.is_armed());
+    assert_eq!(readable(&epoll), 1);
+
+    assert_eq!(waker.clear().unwrap(), 1);
+    assert_eq!(readable(&epoll), 0);
+    assert_eq!(waker.clear().unwrap(), 0);
+}
+
+#[test]
+fn recv_without_fd() {
+    let (a, b) = UnixStream::pair().unwrap();
+    std::io::Write::write_all(&mut &a, &[0]).unwrap();
+    assert!(EventNotifier::recv(&b).is_err());
+}
@@ -0,0 +1,71 @@
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use nix::libc;
+use shmoo::error::ErrorKind;
+use shmoo::event::EventNotifier;
+use shmoo::queue::MsgQueue;
+
+fn name(test: &str) -> String {
+    format!("/shmoo_queue_{}_{}", test, std::process::id())
+}
+
+#[test]
+fn queue_round_trip() {
+    let name = name("round_trip");
+    let mut queue = MsgQueue::<u64>::new(&name, 2).unwrap();
+    assert!(matches!(
+        queue.try_recv().err().unwrap().kind(),
+        ErrorKind::QueueEmpty
+    ));
+
+    let mut peer = MsgQueue::<u64>::open(&name).unwrap();
+    assert_eq!(peer.capacity(), 2);
+    peer.send(1).unwrap();
+    peer.send(2).unwrap();
+    assert!(queue.is_full());
+    assert!(matches!(
+        peer.try_send(3).err().unwrap().kind(),
+        ErrorKind::QueueFull
+    ));
+    assert_eq!(queue.recv().unwrap(), 1);
+    assert_eq!(queue.recv().unwrap(), 2);
+    assert!(peer.is_empty());
+}
+
+#[test]
+fn queue_rejects_zero_capacity() {
+    let err = MsgQueue::<u64>::new(&name("zero"), 0).err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EINVAL)));
+}
+
+#[test]
+fn queue_blocks_on_waker() {
+    // Each side wakes on its own eventfd and notifies the other's.
+    let notifiers = || {
+        let (a, b) = UnixStream::pair().unwrap();
+        let waker = EventNotifier::new().unwrap();
+        waker.send(&a).unwrap();
+        (waker, EventNotifier::recv(&b).unwrap())
+    };
+    let (waker, peer_notifier) = notifiers();
+    let (peer_waker, notifier) = notifiers();
+
+    let name = name("blocks");
+    let mut queue = MsgQueue::<u64>::new(&name, 1).unwrap();
+    queue.set_waker(waker);
+    queue.set_peer(notifier);
+    // With room for one message, the sender waits for each receive as well.
+    let peer = thread::spawn(move || {
+        let mut peer = MsgQueue::<u64>::open(&name).unwrap();
+        peer.set_waker(peer_waker);
+        peer.set_peer(peer_notifier);
+        for i in 0..100 {
+            peer.send(i).unwrap();
+        }
+    });
+    for i in 0..100 {
+        assert_eq!(queue.recv().unwrap(), i);
+    }
+    peer.join().unwrap();
+}
@@ -0,0 +1,819 @@
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::mem::{offset_of, MaybeUninit};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+
+use nix::libc;
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::unistd::ftruncate;
+use shmoo::backend::{Fd, HugePageSize, HugeTlbFs, SysV};
+use shmoo::error::ErrorKind;
+use shmoo::numa::NumaPolicy;
+use shmoo::sync::{BinarySemaphore, PosixMutex, QueueLock, TicketLock};
+use shmoo::{FromShm, SealFlag, Shm, ShmInit, ShmSafe, TypedShm};
+
+#[test]
+fn anonymous_segment() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    assert_eq!(shm.len(), 64);
+    shm[..4].copy_from_slice(b"shmo");
+    assert_eq!(&shm[..4], b"shmo");
+
+    let link = std::fs::read_link(format!("/proc/self/fd/{}", shm.as_raw_fd())).unwrap();
+    assert!(link.to_string_lossy().starts_with("/memfd:shmoo"));
+}
+
+#[test]
+fn open_named_segment() {
+    let mut shm = Shm::new("shmoo_test_open_named", 64).unwrap();
+    shm[..4].copy_from_slice(b"shmo");
+    let peer = Shm::open("shmoo_test_open_named").unwrap();
+    assert_eq!(peer.len(), 64);
+    assert_eq!(&peer[..4], b"shmo");
+}
+
+#[test]
+fn open_keeps_constructed_objects() {
+    let mut shm = Shm::new("shmoo_test_open_keeps", 64).unwrap();
+    shm.construct_mut::<Counter>().unwrap().value = 3;
+
+    let peer = Shm::open("shmoo_test_open_keeps").unwrap();
+    assert_eq!(Counter::from_shm(&peer).unwrap().value, 3);
+    drop(peer);
+
+    let peer = Shm::open("shmoo_test_open_keeps").unwrap();
+    assert_eq!(Counter::from_shm(&peer).unwrap().value, 3);
+}
+
+#[test]
+fn error_into_io_error() {
+    let err: std::io::Error = Shm::open("shmoo_test_missing").err().unwrap().into();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+    let err: std::io::Error = Shm::anonymous(4)
+        .unwrap()
+        .construct::<Counter>()
+        .err()
+        .unwrap()
+        .into();
+    assert_eq!(err.kind(), std::io::ErrorKind::Other);
+}
+
+#[test]
+fn send_segment() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let mut shm = Shm::anonymous(64).unwrap();
+    shm[..4].copy_from_slice(b"shmo");
+    shm.send(&a).unwrap();
+
+    let mut peer = Shm::recv(&b).unwrap();
+    assert_eq!(peer.len(), 64);
+    assert_eq!(&peer[..4], b"shmo");
+    peer[..4].copy_from_slice(b"SHMO");
+    assert_eq!(&shm[..4], b"SHMO");
+}
+
+#[test]
+fn from_fd_rejects_foreign_file() {
+    let fd = memfd_create(c"foreign", MemFdCreateFlag::MFD_CLOEXEC).unwrap();
+    assert!(Shm::from_fd(fd).is_err());
+
+    let fd = memfd_create(c"foreign", MemFdCreateFlag::MFD_CLOEXEC).unwrap();
+    ftruncate(&fd, 4096).unwrap();
+    let err = Shm::from_fd(fd).err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::HeaderError));
+}
+
+#[test]
+fn sealed_segment() {
+    let seals = SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW;
+    let shm = Shm::options()
+        .read(true)
+        .write(true)
+        .seal(seals)
+        .map_anonymous(64)
+        .unwrap();
+    assert!(shm.seals().unwrap().contains(seals));
+    assert!(ftruncate(&shm, 0).is_err());
+
+    let (a, b) = UnixStream::pair().unwrap();
+    shm.send(&a).unwrap();
+    let err = Shm::options()
+        .read(true)
+        .require_seals(SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_SEAL)
+        .recv_ref(&b)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), ErrorKind::SealError(s) if *s == SealFlag::F_SEAL_SEAL));
+
+    shm.send(&a).unwrap();
+    Shm::options()
+        .read(true)
+        .require_seals(SealFlag::F_SEAL_SHRINK)
+        .recv_ref(&b)
+        .unwrap();
+}
+
+#[test]
+fn write_sealed_segment() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    shm[..4].copy_from_slice(b"shmo");
+    let err = shm.seal(SealFlag::F_SEAL_WRITE).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EINVAL)));
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .seal(SealFlag::F_SEAL_WRITE)
+        .map_anonymous(64)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EINVAL)));
+    let sealed = shm.seal_write(SealFlag::F_SEAL_SEAL).unwrap();
+    assert_eq!(&sealed[..4], b"shmo");
+    assert!(sealed
+        .seals()
+        .unwrap()
+        .contains(SealFlag::F_SEAL_WRITE | SealFlag::F_SEAL_SEAL));
+
+    let fd = sealed.fd().unwrap().try_clone_to_owned().unwrap();
+    assert!(Shm::from_fd(fd).is_err());
+    let fd = sealed.fd().unwrap().try_clone_to_owned().unwrap();
+    let peer = Shm::options()
+        .require_seals(SealFlag::F_SEAL_WRITE)
+        .open_backend_ref(Fd::new(fd))
+        .unwrap();
+    assert_eq!(&peer[..4], b"shmo");
+}
+
+#[test]
+fn write_seal_refused() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    shm[..4].copy_from_slice(b"shmo");
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let writer = Shm::from_fd(fd).unwrap();
+    let err = shm.seal_write(SealFlag::empty()).err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EBUSY)));
+    assert_eq!(&writer[..4], b"shmo");
+}
+
+#[test]
+fn inherit_segment() {
+    let shm = Shm::anonymous(64).unwrap();
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "test -e /proc/self/fd/$SHMOO_FD"]);
+    shm.inherit(&mut cmd).unwrap();
+    assert!(cmd.status().unwrap().success());
+
+    // The parent's descriptor is still closed on exec.
+    let mut cmd = Command::new("sh");
+    cmd.args([
+        "-c",
+        &format!("test -e /proc/self/fd/{}", shm.fd().unwrap().as_raw_fd()),
+    ]);
+    assert!(!cmd.status().unwrap().success());
+}
+
+#[test]
+fn inherit_outlives_segment() {
+    let shm = Shm::anonymous(64).unwrap();
+    let mut cmd = Command::new("sh");
+    cmd.args([
+        "-c",
+        "readlink /proc/self/fd/$SHMOO_FD | grep -q '^/memfd:shmoo'",
+    ]);
+    shm.inherit(&mut cmd).unwrap();
+    drop(shm);
+    // Open a descriptor that could otherwise reuse the segment's number.
+    let _file = std::fs::File::open("/dev/null").unwrap();
+    assert!(cmd.status().unwrap().success());
+}
+
+#[test]
+fn inherited_socket_refused() {
+    let (a, _b) = UnixStream::pair().unwrap();
+    let var = "SHMOO_TEST_SOCKET";
+    std::env::set_var(var, a.as_raw_fd().to_string());
+    let err = unsafe { Shm::options().read(true).map_inherited(var) }
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EBADF)));
+    // The socket was not taken over and closed.
+    assert!(std::env::var(var).is_ok());
+    a.set_nonblocking(true).unwrap();
+}
+
+#[test]
+fn shared_anonymous_segment() {
+    let mut shm = Shm::shared_anonymous(64).unwrap();
+    assert!(shm.fd().is_none());
+    unsafe {
+        match libc::fork() {
+            0 => {
+                shm[..4].copy_from_slice(b"shmo");
+                libc::_exit(0);
+            }
+            pid => {
+                assert!(pid > 0);
+                let mut status = 0;
+                libc::waitpid(pid, &mut status, 0);
+            }
+        }
+    }
+    assert_eq!(&shm[..4], b"shmo");
+}
+
+#[derive(ShmInit, FromShm, Default, Clone, Copy)]
+#[repr(C)]
+struct Counter {
+    value: u64,
+}
+
+#[test]
+fn resize_segment() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    let counter = shm.construct_handle::<Counter>().unwrap();
+
+    let (a, b) = UnixStream::pair().unwrap();
+    shm.send(&a).unwrap();
+    let mut peer = Shm::recv(&b).unwrap();
+    let peer_counter = peer.handle::<Counter>().unwrap();
+
+    shm.resize(1 << 20).unwrap();
+    assert_eq!(shm.len(), (1 << 20) - size_of::<Counter>());
+    shm.get_mut(counter).unwrap().value = 7;
+
+    assert_eq!(peer.len(), 64);
+    assert_eq!(peer.get_mut(peer_counter).unwrap().value, 7);
+    assert_eq!(peer.len(), 1 << 20);
+    assert!(!peer.refresh().unwrap());
+
+    assert!(shm.resize(0).is_err());
+    shm.resize(size_of::<Counter>()).unwrap();
+    assert_eq!(peer.get(peer_counter).unwrap().value, 7);
+    assert_eq!(peer.len(), size_of::<Counter>());
+    assert!(!peer.refresh().unwrap());
+}
+
+#[test]
+fn file_backed_segment() {
+    let path = std::env::temp_dir().join(format!("shmoo_file_{}", std::process::id()));
+    {
+        let mut shm = Shm::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .map_file(&path, 8192)
+            .unwrap();
+        shm.construct_mut::<Counter>().unwrap().value = 42;
+        shm.flush_range(0, size_of::<Counter>()).unwrap();
+        shm.flush_range(4096, 4096).unwrap();
+        assert!(shm.flush_range(4096, 4097).is_err());
+        shm.flush_async().unwrap();
+        shm.flush().unwrap();
+    }
+
+    let shm = Shm::options()
+        .read(true)
+        .write(true)
+        .open_file(&path)
+        .unwrap();
+    assert_eq!(Counter::from_shm(&shm).unwrap().value, 42);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sysv_segment() {
+    let key = std::process::id() as libc::key_t;
+    let mut shm = Shm::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .exclusive(true)
+        .map_backend(SysV::new(key), 64)
+        .unwrap();
+    shm.construct_mut::<Counter>().unwrap().value = 7;
+    assert!(shm.fd().is_none());
+    assert!(shm.resize(128).is_err());
+
+    let peer = Shm::options()
+        .read(true)
+        .write(true)
+        .open_backend(SysV::new(key))
+        .unwrap();
+    assert_eq!(Counter::from_shm(&peer).unwrap().value, 7);
+    drop(peer);
+
+    // Neither creating over the key nor dropping a peer may disturb the segment.
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .map_backend(SysV::new(key), 64)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EEXIST)));
+    let peer = Shm::options().open_backend_ref(SysV::new(key)).unwrap();
+    assert_eq!(peer.read::<Counter>().unwrap().value, 7);
+}
+
+#[test]
+fn hugetlbfs_rejects_regular_file() {
+    let path = std::env::temp_dir().join(format!("shmoo_huge_{}", std::process::id()));
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .map_backend(HugeTlbFs::new(&path), 64)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EINVAL)));
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn map_error(err: shmoo::Error) -> &'static str {
+    match err.kind() {
+        ErrorKind::MapError(option, _) => option,
+        _ => panic!("expected a map error, got: {}", err),
+    }
+}
+
+#[test]
+fn mapping_options() {
+    let mut shm = Shm::options()
+        .read(true)
+        .write(true)
+        .populate(true)
+        .no_reserve(true)
+        .lock(true)
+        .map_anonymous(4096)
+        .unwrap();
+    shm.construct_mut::<Counter>().unwrap().value = 1;
+
+    let thp = Shm::options()
+        .read(true)
+        .write(true)
+        .transparent_huge_pages(true)
+        .map_anonymous(4096);
+    if let Err(err) = thp {
+        assert_eq!(map_error(err), "MADV_HUGEPAGE");
+    }
+}
+
+#[test]
+fn huge_page_segment() {
+    for size in [HugePageSize::Default, HugePageSize::Size2MiB] {
+        match Shm::options()
+            .read(true)
+            .write(true)
+            .huge_pages(size)
+            .map_anonymous(4096)
+        {
+            Ok(mut shm) => shm.construct_mut::<Counter>().unwrap().value = 1,
+            Err(err) => assert_eq!(map_error(err), "MAP_HUGETLB"),
+        }
+        match Shm::options()
+            .read(true)
+            .write(true)
+            .huge_pages(size)
+            .map_shared_anonymous(4096)
+        {
+            Ok(mut shm) => shm.construct_mut::<Counter>().unwrap().value = 1,
+            Err(err) => assert_eq!(map_error(err), "MAP_HUGETLB"),
+        }
+    }
+
+    // POSIX segments are never backed by hugetlbfs.
+    let name = format!("shmoo_huge_{}", std::process::id());
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .huge_pages(HugePageSize::Default)
+        .map(&name, 4096)
+        .err()
+        .unwrap();
+    assert_eq!(map_error(err), "MAP_HUGETLB");
+
+    // Failing to map an existing segment must not remove it.
+    let _shm = Shm::new(&name, 4096).unwrap();
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .huge_pages(HugePageSize::Default)
+        .open_typed::<Counter>(&name)
+        .err()
+        .unwrap();
+    assert_eq!(map_error(err), "MAP_HUGETLB");
+    Shm::open_private(&name).unwrap();
+}
+
+#[test]
+fn numa_policy() {
+    for policy in [
+        NumaPolicy::Bind(vec![0]),
+        NumaPolicy::Interleave(vec![0]),
+        NumaPolicy::Preferred(0),
+    ] {
+        let mut shm = Shm::options()
+            .read(true)
+            .write(true)
+            .numa(policy)
+            .map_anonymous(8192)
+            .unwrap();
+        shm.construct_mut::<Counter>().unwrap().value = 1;
+        assert_eq!(shm.numa_nodes().unwrap(), [0]);
+    }
+
+    let err = Shm::options()
+        .read(true)
+        .write(true)
+        .numa(NumaPolicy::Bind(vec![1023]))
+        .map_anonymous(8192)
+        .err()
+        .unwrap();
+    assert_eq!(map_error(err), "mbind");
+}
+
+#[test]
+fn private_snapshot() {
+    let name = format!("shmoo_private_{}", std::process::id());
+    let mut shm = Shm::new(&name, 64).unwrap();
+    let counter = shm.construct_handle::<Counter>().unwrap();
+    shm.get_mut(counter).unwrap().value = 1;
+
+    let mut snapshot = shm.snapshot().unwrap();
+    Counter::from_shm_mut(&mut snapshot).unwrap().value = 5;
+    assert_eq!(shm.get(counter).unwrap().value, 1);
+    assert!(snapshot.resize(128).is_err());
+
+    let mut private = Shm::open_private(&name).unwrap();
+    assert_eq!(Counter::from_shm(&private).unwrap().value, 1);
+    Counter::from_shm_mut(&mut private).unwrap().value = 6;
+    drop(private);
+    assert_eq!(shm.get(counter).unwrap().value, 1);
+    assert_eq!(Counter::from_shm(&snapshot).unwrap().value, 5);
+
+    // Dropping the private view must not have unlinked the segment.
+    Shm::open_private(&name).unwrap();
+}
+
+#[test]
+fn read_only_segment() {
+    let name = format!("shmoo_ref_{}", std::process::id());
+    let mut shm = Shm::new(&name, 64).unwrap();
+    let counter = shm.construct_handle::<Counter>().unwrap();
+    shm.get_mut(counter).unwrap().value = 3;
+
+    // A Shm hands out mutable references, so it cannot map the segment read-only.
+    let err = Shm::options().read(true).open(&name).err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::IoError(e) if e.raw_os_error() == Some(libc::EACCES)));
+
+    let mut reader = Shm::open_ref(&name).unwrap();
+    assert_eq!(reader.read::<Counter>().unwrap().value, 3);
+    assert_eq!(reader.get(counter).unwrap().value, 3);
+    assert_eq!(reader[..8], 3u64.to_ne_bytes());
+    drop(reader);
+
+    // The reader must leave the segment in place, even if it fails to map it.
+    let err = Shm::options()
+        .huge_pages(HugePageSize::Default)
+        .open_ref(&name)
+        .err()
+        .unwrap();
+    assert_eq!(map_error(err), "MAP_HUGETLB");
+    shm.get_mut(counter).unwrap().value = 4;
+    let reader = Shm::open_ref(&name).unwrap();
+    assert_eq!(reader.read::<Counter>().unwrap().value, 4);
+}
+
+#[test]
+fn cursor_io() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(shm.cursor().read_to_end(&mut buf).unwrap(), 64);
+    assert_eq!(buf, [0; 64]);
+
+    let mut cursor = shm.cursor();
+    cursor.write_all(b"first\nsecond\n").unwrap();
+    assert_eq!(cursor.position(), 13);
+    cursor.seek(SeekFrom::Start(0)).unwrap();
+    let mut line = String::new();
+    cursor.read_line(&mut line).unwrap();
+    assert_eq!(line, "first\n");
+    cursor.seek(SeekFrom::Current(1)).unwrap();
+    let mut word = [0; 5];
+    cursor.read_exact(&mut word).unwrap();
+    assert_eq!(&word, b"econd");
+
+    cursor.write_all_at(b"FIRST", 0).unwrap();
+    let mut head = [0; 5];
+    cursor.read_exact_at(&mut head, 0).unwrap();
+    assert_eq!(&head, b"FIRST");
+    assert_eq!(cursor.position(), 12);
+    assert!(cursor.write_all_at(b"xx", 63).is_err());
+    assert!(cursor.read_exact_at(&mut head, 60).is_err());
+
+    assert_eq!(cursor.seek(SeekFrom::End(-2)).unwrap(), 62);
+    assert!(cursor.write_all(b"xyz").is_err());
+    assert_eq!(cursor.position(), 64);
+    assert_eq!(cursor.read(&mut word).unwrap(), 0);
+    assert!(cursor.seek(SeekFrom::Current(-65)).is_err());
+    assert_eq!(&shm[..5], b"FIRST");
+    assert_eq!(&shm[62..], b"xy");
+}
+
+#[test]
+fn typed_segment() {
+    let name = format!("shmoo_typed_{}", std::process::id());
+    let mut counter = TypedShm::<Counter>::new(&name).unwrap();
+    counter.value = 9;
+
+    let mut peer = TypedShm::<Counter>::open(&name).unwrap();
+    assert_eq!(peer.value, 9);
+    peer.value += 1;
+    assert_eq!(counter.value, 10);
+
+    // A segment that fails to open as a T must be left in place for its owner.
+    assert!(TypedShm::<Large>::open(&name).is_err());
+    assert_eq!(TypedShm::<Counter>::open(&name).unwrap().value, 10);
+
+    let anonymous = TypedShm::<Counter>::anonymous().unwrap();
+    let fd = anonymous.shm().fd().unwrap().try_clone_to_owned().unwrap();
+    let mut peer = TypedShm::<Counter>::from_fd(fd).unwrap();
+    peer.value = 2;
+    assert_eq!(anonymous.value, 2);
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+struct Large {
+    lock: QueueLock<4>,
+    mutex: PosixMutex,
+    buf: [u8; 64 << 20],
+}
+
+#[test]
+fn construct_in_place() {
+    // Nothing may be built on this thread's small stack.
+    let thread = std::thread::Builder::new().stack_size(64 << 10);
+    let handle = thread
+        .spawn(|| {
+            let mut shm = Shm::anonymous(size_of::<Large>() + align_of::<Large>()).unwrap();
+            let large = shm.construct_mut::<Large>().unwrap();
+            large.lock.lock().unwrap();
+            large.lock.unlock().unwrap();
+            large.mutex.lock().unwrap();
+            large.mutex.unlock().unwrap();
+            assert_eq!(large.buf[0], 0);
+            assert_eq!(large.buf[(64 << 20) - 1], 0);
+
+            let mut shm = Shm::anonymous(size_of::<[u64; 1 << 20]>()).unwrap();
+            let buf = unsafe {
+                shm.construct_with::<[u64; 1 << 20], _>(|buf| {
+                    buf.as_mut_ptr().cast::<u64>().write(7);
+                    Ok(())
+                })
+            }
+            .unwrap();
+            assert_eq!(buf[0], 7);
+            assert_eq!(buf[1], 0);
+            assert!(shm.construct::<Counter>().is_err());
+        })
+        .unwrap();
+    handle.join().unwrap();
+}
+
+fn init_ticket(lock: &mut MaybeUninit<TicketLock>) -> shmoo::error::Result<()> {
+    lock.write(TicketLock::new());
+    Ok(())
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+#[shm(capacity = size_of::<Self>() + 64)]
+struct Configured {
+    #[shm(default = 42)]
+    answer: u64,
+    #[shm(zeroed)]
+    raw: [u32; 4],
+    #[shm(init = init_ticket)]
+    lock: TicketLock,
+    #[shm(skip)]
+    kept: [u8; 8],
+    sem: BinarySemaphore,
+}
+
+#[test]
+fn derive_field_attributes() {
+    let len = size_of::<Configured>() + 64;
+    let mut shm = Shm::anonymous(len).unwrap();
+    shm[..len].fill(0xff);
+    shm[offset_of!(Configured, kept)..][..8].copy_from_slice(b"keepkeep");
+
+    let obj = shm.construct_mut::<Configured>().unwrap();
+    assert_eq!(obj.answer, 42);
+    assert_eq!(obj.raw, [0; 4]);
+    obj.lock.lock().unwrap();
+    obj.lock.unlock().unwrap();
+    assert_eq!(&obj.kept, b"keepkeep");
+    assert!(!obj.sem.try_wait());
+    assert_eq!(shm.len(), 0);
+}
+
+#[derive(ShmInit, FromShm, Debug, PartialEq)]
+#[repr(C, u8)]
+enum Slot {
+    Empty,
+    #[shm(default)]
+    Ready {
+        #[shm(default = 7)]
+        id: u32,
+        data: [u64; 2],
+    },
+    Done(u16),
+}
+
+#[derive(ShmInit, FromShm, ShmSafe, Debug, PartialEq)]
+#[repr(u32)]
+enum Phase {
+    Idle = 3,
+    Busy,
+    Stopped = 10,
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+union Word {
+    bytes: [u8; 8],
+    #[shm(default = 0x0102_0304)]
+    int: u64,
+}
+
+#[test]
+fn derive_enums_and_unions() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    let slot = shm.construct_mut::<Slot>().unwrap();
+    assert_eq!(
+        *slot,
+        Slot::Ready {
+            id: 7,
+            data: [0; 2]
+        }
+    );
+    assert_ne!(*slot, Slot::Empty);
+    *slot = Slot::Done(5);
+    let word = shm.construct::<Word>().unwrap();
+    assert_eq!(unsafe { word.int }, 0x0102_0304);
+    assert_eq!(*shm.construct::<Phase>().unwrap(), Phase::Idle);
+
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let mut peer = Shm::from_fd(fd).unwrap();
+    assert_eq!(*Slot::from_shm(&peer).unwrap(), Slot::Done(5));
+    peer[0] = 9;
+    let err = Slot::from_shm(&peer).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::DiscriminantError(9)));
+
+    let mut shm = Shm::anonymous(4).unwrap();
+    shm[..4].copy_from_slice(&11u32.to_ne_bytes());
+    let err = Phase::from_shm(&shm).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::DiscriminantError(11)));
+    shm[..4].copy_from_slice(&4u32.to_ne_bytes());
+    assert_eq!(*Phase::from_shm(&shm).unwrap(), Phase::Busy);
+    shm[..4].copy_from_slice(&10u32.to_ne_bytes());
+    assert_eq!(*Phase::from_shm(&shm).unwrap(), Phase::Stopped);
+}
+
+#[derive(ShmInit, FromShm, Debug)]
+#[repr(C)]
+struct Checked {
+    flags: [bool; 2],
+    letter: char,
+    phase: Phase,
+}
+
+#[test]
+fn derive_validates_fields() {
+    let mut shm = Shm::anonymous(64).unwrap();
+    shm.construct::<Checked>().unwrap();
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let mut peer = Shm::from_fd(fd).unwrap();
+    assert!(Checked::from_shm(&peer).is_ok());
+
+    peer[1] = 2;
+    let err = Checked::from_shm(&peer).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::ValueError("bool")));
+    peer[1] = 1;
+
+    let letter = offset_of!(Checked, letter);
+    peer[letter..letter + 4].copy_from_slice(&0xd800u32.to_ne_bytes());
+    let err = Checked::from_shm(&peer).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::ValueError("char")));
+    peer[letter..letter + 4].copy_from_slice(&('x' as u32).to_ne_bytes());
+
+    let phase = offset_of!(Checked, phase);
+    peer[phase..phase + 4].copy_from_slice(&11u32.to_ne_bytes());
+    let err = Checked::from_shm(&peer).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::DiscriminantError(11)));
+    peer[phase..phase + 4].copy_from_slice(&4u32.to_ne_bytes());
+    let checked = Checked::from_shm(&peer).unwrap();
+    assert_eq!((checked.flags, checked.letter), ([false, true], 'x'));
+    assert_eq!(checked.phase, Phase::Busy);
+
+    // A variant's fields are checked too.
+    let mut shm = Shm::anonymous(64).unwrap();
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let toggle = shm.construct_mut::<Toggle>().unwrap();
+    assert_eq!(*toggle, Toggle::Off);
+    *toggle = Toggle::On(true);
+    let mut peer = Shm::from_fd(fd).unwrap();
+    assert_eq!(*Toggle::from_shm(&peer).unwrap(), Toggle::On(true));
+    peer[1] = 3;
+    let err = Toggle::from_shm(&peer).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::ValueError("bool")));
+}
+
+#[derive(ShmInit, FromShm, Debug, PartialEq)]
+#[repr(u8)]
+enum Toggle {
+    Off,
+    On(bool),
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C, align(64))]
+struct CacheLine {
+    #[shm(default = 1)]
+    value: u64,
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(transparent)]
+struct Wrapper(u32);
+
+#[derive(ShmInit, FromShm)]
+#[repr(C, packed)]
+struct Packed {
+    tag: u8,
+    #[shm(default = 9)]
+    value: u32,
+}
+
+#[test]
+fn derive_repr_options() {
+    let mut shm = Shm::anonymous(4096).unwrap();
+    let wrapper = shm.construct_mut::<Wrapper>().unwrap();
+    wrapper.0 = 3;
+    let line = shm.construct_handle::<CacheLine>().unwrap();
+    let line = shm.get(line).unwrap();
+    assert_eq!(line.value, 1);
+    assert!((line as *const CacheLine).is_aligned());
+
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let peer = Shm::from_fd(fd).unwrap();
+    assert_eq!(Wrapper::from_shm(&peer).unwrap().0, 3);
+
+    let mut shm = Shm::anonymous(128).unwrap();
+    assert!(CacheLine::from_shm(&shm).is_ok());
+    // The value field follows a single byte, so it is unaligned.
+    let packed = shm.construct_mut::<Packed>().unwrap();
+    assert_eq!((packed.tag, { packed.value }), (0, 9));
+    packed.value = 10;
+
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let peer = Shm::from_fd(fd).unwrap();
+    assert_eq!({ Packed::from_shm(&peer).unwrap().value }, 10);
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C, align(4096))]
+struct Page {
+    #[shm(default = 5)]
+    value: u16,
+}
+
+#[test]
+fn construct_alignment() {
+    let mut shm = Shm::anonymous(3 * 4096).unwrap();
+    let mut addrs = [0usize; 4];
+    addrs[0] = unsafe { shm.construct_with::<[u8; 3], _>(|_| Ok(())) }.unwrap() as *mut _ as usize;
+    addrs[1] = shm.construct::<Page>().unwrap() as *const _ as usize;
+    addrs[2] = shm.construct::<Wrapper>().unwrap() as *const _ as usize;
+    addrs[3] = shm.construct::<CacheLine>().unwrap() as *const _ as usize;
+    assert_eq!(addrs[1] % 4096, 0);
+    assert_eq!(addrs[2], addrs[1] + 4096);
+    assert_eq!(addrs[3], addrs[2] + 64);
+    assert!(addrs[0] < addrs[1]);
+
+    let mut shm = Shm::anonymous(4096).unwrap();
+    let err = shm.construct::<Page>().err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::AlignmentError(4096)));
+    let err = Page::from_shm(&shm).err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::AlignmentError(4096)));
+    let line = shm.construct::<CacheLine>().unwrap();
+    assert_eq!(line.value, 1);
+
+    let mut shm = Shm::anonymous(16).unwrap();
+    let err = shm.construct::<CacheLine>().err().unwrap();
+    assert!(matches!(err.kind(), ErrorKind::SizeError(16)));
+}
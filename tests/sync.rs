@@ -0,0 +1,238 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use nix::errno::Errno;
+use shmoo::error::ErrorKind;
+use shmoo::sync::{
+    MutexProtocol, MutexType, PosixCondition, PosixMutex, QueueLock, Spinlock, TicketLock,
+};
+use shmoo::Shm;
+
+fn sync_error(err: shmoo::Error) -> (&'static str, Errno) {
+    match err.kind() {
+        ErrorKind::SyncError(op, errno) => (*op, *errno),
+        _ => panic!("expected a sync error, got: {}", err),
+    }
+}
+
+#[test]
+fn errorcheck_mutex_double_lock() {
+    let mut mtx = PosixMutex::options()
+        .kind(MutexType::ErrorCheck)
+        .build()
+        .unwrap();
+    mtx.lock().unwrap();
+    let err = mtx.lock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_lock", Errno::EDEADLK));
+    mtx.unlock().unwrap();
+}
+
+#[test]
+fn errorcheck_mutex_unlock_unowned() {
+    let mut mtx = PosixMutex::options()
+        .kind(MutexType::ErrorCheck)
+        .build()
+        .unwrap();
+    let err = mtx.unlock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_unlock", Errno::EPERM));
+}
+
+#[test]
+fn recursive_mutex_relock() {
+    let mut mtx = PosixMutex::options()
+        .kind(MutexType::Recursive)
+        .build()
+        .unwrap();
+    mtx.lock().unwrap();
+    mtx.lock().unwrap();
+    mtx.unlock().unwrap();
+    mtx.unlock().unwrap();
+    let err = mtx.unlock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_unlock", Errno::EPERM));
+}
+
+#[test]
+fn robust_mutex_owner_dead() {
+    let mut mtx = PosixMutex::options().robust(true).build().unwrap();
+    std::thread::scope(|s| {
+        s.spawn(|| mtx.lock().unwrap());
+    });
+    let err = mtx.lock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_lock", Errno::EOWNERDEAD));
+    mtx.consistent().unwrap();
+    mtx.unlock().unwrap();
+    mtx.lock().unwrap();
+    mtx.unlock().unwrap();
+}
+
+#[test]
+fn robust_mutex_in_segment() {
+    let mut shm = Shm::anonymous(size_of::<PosixMutex>() * 2).unwrap();
+    let mtx = unsafe {
+        shm.construct_with::<PosixMutex, _>(|mtx| {
+            PosixMutex::options()
+                .robust(true)
+                .build_in(mtx.as_mut_ptr())
+        })
+    }
+    .unwrap();
+    std::thread::scope(|s| {
+        s.spawn(|| mtx.lock().unwrap());
+    });
+    let err = mtx.lock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_lock", Errno::EOWNERDEAD));
+    mtx.consistent().unwrap();
+    mtx.unlock().unwrap();
+}
+
+#[test]
+fn priority_inherit_mutex() {
+    let mut mtx = PosixMutex::options()
+        .kind(MutexType::ErrorCheck)
+        .protocol(MutexProtocol::Inherit)
+        .build()
+        .unwrap();
+    mtx.lock().unwrap();
+    let err = mtx.lock().unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_mutex_lock", Errno::EDEADLK));
+    mtx.unlock().unwrap();
+}
+
+#[test]
+fn priority_protect_mutex() {
+    PosixMutex::options()
+        .protocol(MutexProtocol::Protect(1))
+        .build()
+        .unwrap();
+}
+
+#[test]
+fn condition_wait_unowned_mutex() {
+    let mut mtx = PosixMutex::options()
+        .kind(MutexType::ErrorCheck)
+        .build()
+        .unwrap();
+    let mut cond = PosixCondition::new().unwrap();
+    let err = cond.wait(&mut mtx).unwrap_err();
+    assert_eq!(sync_error(err), ("pthread_cond_wait", Errno::EPERM));
+}
+
+#[test]
+fn spinlock_unlock_unowned() {
+    let mut lock = Spinlock::new();
+    let err = lock.unlock().unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::IoError(_)));
+    lock.lock().unwrap();
+    lock.unlock().unwrap();
+}
+
+#[test]
+fn ticket_lock_unlock_unlocked() {
+    let mut lock = TicketLock::new();
+    assert!(lock.unlock().is_err());
+    lock.lock().unwrap();
+    lock.unlock().unwrap();
+    assert!(lock.unlock().is_err());
+}
+
+#[test]
+fn queue_lock_wraps_around() {
+    let mut lock = QueueLock::<4>::new();
+    assert!(lock.unlock().is_err());
+    for _ in 0..10 {
+        lock.lock().unwrap();
+        lock.unlock().unwrap();
+    }
+    assert!(lock.unlock().is_err());
+}
+
+#[test]
+fn queue_lock_mutual_exclusion() {
+    struct Shared(std::cell::UnsafeCell<(QueueLock<4>, u32)>);
+    unsafe impl Sync for Shared {}
+
+    impl Shared {
+        #[allow(clippy::mut_from_ref)]
+        fn get(&self) -> &mut (QueueLock<4>, u32) {
+            unsafe { &mut *self.0.get() }
+        }
+    }
+
+    let shared = Shared(std::cell::UnsafeCell::new((QueueLock::new(), 0)));
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let (lock, count) = shared.get();
+                    lock.lock().unwrap();
+                    *count += 1;
+                    lock.unlock().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(shared.0.into_inner().1, 400);
+}
+
+#[test]
+fn queue_lock_contended() {
+    struct Shared(std::cell::UnsafeCell<QueueLock<2>>);
+    unsafe impl Sync for Shared {}
+
+    impl Shared {
+        #[allow(clippy::mut_from_ref)]
+        fn get(&self) -> &mut QueueLock<2> {
+            unsafe { &mut *self.0.get() }
+        }
+    }
+
+    // With no more threads than slots, lock must never fail with WouldBlock.
+    let shared = Shared(std::cell::UnsafeCell::new(QueueLock::new()));
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                for _ in 0..2_000 {
+                    shared.get().lock().unwrap();
+                    shared.get().unlock().unwrap();
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn queue_lock_rejects_excess_waiters() {
+    struct Shared(std::cell::UnsafeCell<QueueLock<2>>);
+    unsafe impl Sync for Shared {}
+
+    impl Shared {
+        #[allow(clippy::mut_from_ref)]
+        fn get(&self) -> &mut QueueLock<2> {
+            unsafe { &mut *self.0.get() }
+        }
+
+        /// The lock's tail, its first field, which counts the tickets taken.
+        fn tail(&self) -> &AtomicU32 {
+            unsafe { &*self.0.get().cast::<AtomicU32>() }
+        }
+    }
+
+    assert_eq!(align_of::<QueueLock<2>>(), 64);
+    let shared = Shared(std::cell::UnsafeCell::new(QueueLock::new()));
+    shared.get().lock().unwrap();
+    std::thread::scope(|s| {
+        let waiter = s.spawn(|| {
+            shared.get().lock().unwrap();
+            shared.get().unlock().unwrap();
+        });
+        // Wait for the waiter to take the second ticket.
+        while shared.tail().load(Ordering::Acquire) != 2 {
+            std::hint::spin_loop();
+        }
+        let err = shared.get().lock().unwrap_err();
+        assert!(
+            matches!(err.kind(), ErrorKind::IoError(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+        );
+        shared.get().unlock().unwrap();
+        waiter.join().unwrap();
+    });
+}
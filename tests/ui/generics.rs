@@ -0,0 +1,47 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use shmoo::{FromShm, Shm, ShmInit, ShmSafe};
+
+#[derive(ShmInit, FromShm, ShmSafe)]
+#[repr(C)]
+struct Slot<T> {
+    seq: AtomicU32,
+    value: T,
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+struct Ring<T, const N: usize> {
+    head: usize,
+    slots: [Slot<T>; N],
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C)]
+struct Borrowed<'a, T> {
+    len: u64,
+    _marker: PhantomData<&'a T>,
+}
+
+#[derive(ShmInit, FromShm)]
+#[repr(C, u8)]
+enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+fn main() {
+    let mut shm = Shm::anonymous(4096).unwrap();
+    let ring = shm.construct_mut::<Ring<u64, 8>>().unwrap();
+    ring.slots[3].value = 7;
+    ring.slots[3].seq.store(1, Ordering::Relaxed);
+    let borrowed = shm.construct::<Borrowed<'static, u8>>().unwrap();
+    assert_eq!(borrowed.len, 0);
+    assert!(matches!(shm.construct::<Either<u32, f64>>().unwrap(), Either::Left(0)));
+
+    let fd = shm.fd().unwrap().try_clone_to_owned().unwrap();
+    let peer = Shm::from_fd(fd).unwrap();
+    let ring = Ring::<u64, 8>::from_shm(&peer).unwrap();
+    assert_eq!(ring.slots[3].value, 7);
+}
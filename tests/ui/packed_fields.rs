@@ -0,0 +1,34 @@
+use std::mem::MaybeUninit;
+
+use shmoo::{Shm, ShmInit, ShmSafe};
+
+#[derive(ShmInit, ShmSafe)]
+#[repr(C)]
+struct Version {
+    #[shm(default = 1)]
+    value: u32,
+}
+
+#[derive(ShmInit)]
+#[repr(C, packed)]
+struct Versioned {
+    tag: u8,
+    version: Version,
+}
+
+fn init_value(_: &mut MaybeUninit<u32>) -> shmoo::error::Result<()> {
+    Ok(())
+}
+
+#[derive(ShmInit)]
+#[repr(C, packed)]
+struct Custom {
+    tag: u8,
+    #[shm(init = init_value)]
+    value: u32,
+}
+
+fn main() {
+    let mut shm = Shm::anonymous(4096).unwrap();
+    shm.construct::<Versioned>().unwrap();
+}
@@ -0,0 +1,24 @@
+use shmoo::{FromShm, ShmInit};
+
+#[derive(FromShm)]
+#[repr(C)]
+struct Message<'a> {
+    len: usize,
+    name: String,
+    data: Vec<u8>,
+    next: Box<Message<'a>>,
+    peer: &'a u64,
+}
+
+#[derive(ShmInit)]
+#[repr(C)]
+struct Nested {
+    inner: Inner,
+}
+
+#[repr(C)]
+struct Inner {
+    value: u64,
+}
+
+fn main() {}
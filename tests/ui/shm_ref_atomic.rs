@@ -0,0 +1,14 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use shmoo::{FromShm, Shm};
+
+#[derive(FromShm)]
+#[repr(C)]
+struct Flag {
+    value: AtomicU32,
+}
+
+fn main() {
+    let reader = Shm::open_ref("shmoo_flag").unwrap();
+    reader.read::<Flag>().unwrap().value.store(1, Ordering::Release);
+}
@@ -0,0 +1,12 @@
+use shmoo::{Shm, ShmInit};
+
+#[derive(ShmInit)]
+#[repr(C)]
+struct Slot<T> {
+    value: T,
+}
+
+fn main() {
+    let mut shm = Shm::anonymous(4096).unwrap();
+    shm.construct::<Slot<String>>().unwrap();
+}